@@ -1,18 +1,73 @@
+use std::{num::NonZeroUsize, path::PathBuf};
+
 use clap::Parser;
+use glob::Pattern;
 
-use crate::persistence::files_system::FileType;
+use scylladb_uploader::entities::ErrorBudget;
+use scylladb_uploader::persistence::{ColumnBinding, ConsistencyLevel, FrameCompression, RetryableError, SerialConsistencyLevel, WriteMode};
+use scylladb_uploader::persistence::files_system::{Compression, FileType, JsonPointer};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about=None)]
 pub struct CommandLine {
-    /// Source path
+    /// Source path: a file, a directory, a glob pattern or an `s3://bucket/prefix/`
     #[clap(long, short, env = "SOURCE_PATH")]
     pub source_path: String,
 
+    /// Comma separated glob patterns; only files whose name matches one of them are loaded
+    #[clap(long, value_delimiter = ',', env = "SOURCE_INCLUDE")]
+    pub source_include: Vec<Pattern>,
+
+    /// Comma separated glob patterns; files whose name matches one of them are skipped
+    #[clap(long, value_delimiter = ',', env = "SOURCE_EXCLUDE")]
+    pub source_exclude: Vec<Pattern>,
+
+    /// Number of source files loaded simultaneously (1 loads them in sequence)
+    #[clap(long, default_value = "1", env = "SOURCE_PARALLELISM")]
+    pub source_parallelism: usize,
+
+    /// Threads reading, converting and writing rows (defaults to the number of cores)
+    #[clap(long, env = "WORKER_THREADS")]
+    pub worker_threads: Option<NonZeroUsize>,
+
     /// Source file type
     #[clap(long, default_value = "json", env = "SOURCE_FILE_TYPE")]
     pub source_file_type: FileType,
 
+    /// Source file compression (Parquet files are compressed internally and read as they are)
+    #[clap(long, default_value = "auto", env = "SOURCE_COMPRESSION")]
+    pub source_compression: Compression,
+
+    /// JSON pointer to the records inside each JSON document, like `/data/items`. When it points to an
+    /// array, its elements are the records
+    #[clap(long, env = "JSON_POINTER")]
+    pub json_pointer: Option<JsonPointer>,
+
+    /// CSV field delimiter (a single character, or `\t` for tab separated files)
+    #[clap(long, default_value = ",", value_parser = parse_csv_character, env = "CSV_DELIMITER")]
+    pub csv_delimiter: u8,
+
+    /// CSV quote character
+    #[clap(long, default_value = "\"", value_parser = parse_csv_character, env = "CSV_QUOTE")]
+    pub csv_quote: u8,
+
+    /// CSV escape character for quotes inside quoted fields, besides doubling them
+    #[clap(long, value_parser = parse_csv_character, env = "CSV_ESCAPE")]
+    pub csv_escape: Option<u8>,
+
+    /// Lines starting with this character are ignored in CSV files
+    #[clap(long, value_parser = parse_csv_character, env = "CSV_COMMENT")]
+    pub csv_comment: Option<u8>,
+
+    /// Comma separated column names of headerless CSV files
+    #[clap(long, value_delimiter = ',', env = "CSV_COLUMNS")]
+    pub csv_columns: Option<Vec<String>>,
+
+    /// CSV cell text loaded as null, like the NULL option of cqlsh COPY. Empty cells are null by default;
+    /// set it to another marker, like `NULL`, to load them as empty strings
+    #[clap(long, default_value = "", env = "CSV_NULL")]
+    pub csv_null: String,
+
     /// Database username
     #[clap(long, env = "DATABASE_USERNAME")]
     pub database_username: String,
@@ -33,6 +88,125 @@ pub struct CommandLine {
     #[clap(long, env = "DATABASE_TABLE")]
     pub database_table: String,
 
+    /// Consistency level of the inserts
+    #[clap(long, default_value = "LOCAL_QUORUM", ignore_case = true, env = "CONSISTENCY")]
+    pub consistency: ConsistencyLevel,
+
+    /// Consistency level of the Paxos phase of conditional inserts (the driver's `LOCAL_SERIAL` when unset)
+    #[clap(long, ignore_case = true, env = "SERIAL_CONSISTENCY")]
+    pub serial_consistency: Option<SerialConsistencyLevel>,
+
+    /// Connect to the nodes over TLS. Node certificates are checked against the trusted authorities, and
+    /// against a host name with `--tls-verify-hostname`
+    #[clap(long, env = "TLS")]
+    pub tls: bool,
+
+    /// PEM certificates of the authorities signing the node certificates (the system ones by default)
+    #[clap(long, requires = "tls", env = "TLS_CA_FILE")]
+    pub tls_ca_file: Option<PathBuf>,
+
+    /// PEM certificate chain presented to the nodes, for clusters requiring client certificates
+    #[clap(long, requires_all = ["tls", "tls_key_file"], env = "TLS_CERT_FILE")]
+    pub tls_cert_file: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert-file`
+    #[clap(long, requires_all = ["tls", "tls_cert_file"], env = "TLS_KEY_FILE")]
+    pub tls_key_file: Option<PathBuf>,
+
+    /// Host name every node certificate must match, in its subject alternative names or common name, with
+    /// wildcards allowed. The driver opens the connections to the addresses the cluster reports without telling
+    /// which node each is for, so the nodes are all checked against this one name, as given by a wildcard or
+    /// shared certificate. Without it, any certificate of the trusted authorities is accepted for any node.
+    #[clap(long, requires = "tls", conflicts_with = "tls_insecure_skip_verify", env = "TLS_VERIFY_HOSTNAME")]
+    pub tls_verify_hostname: Option<String>,
+
+    /// INSECURE: disable all validation of the node certificates, accepting expired, self-signed and
+    /// untrusted ones, so that anyone on the network path can read and alter the traffic. Only for test
+    /// clusters.
+    #[clap(long, requires = "tls", env = "TLS_INSECURE_SKIP_VERIFY")]
+    pub tls_insecure_skip_verify: bool,
+
+    /// Datacenter the inserts are sent to, leaving the nodes of the other ones out
+    #[clap(long, env = "LOCAL_DATACENTER")]
+    pub local_datacenter: Option<String>,
+
+    /// Rack of the local datacenter whose replicas are tried first
+    #[clap(long, requires = "local_datacenter", env = "PREFERRED_RACK")]
+    pub preferred_rack: Option<String>,
+
+    /// Send each insert to the replicas of its partition
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set, env = "TOKEN_AWARE")]
+    pub token_aware: bool,
+
+    /// Open the connections to each shard of the nodes, through the shard-aware port, rather than to the nodes
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set, env = "SHARD_AWARE")]
+    pub shard_aware: bool,
+
+    /// Connections to each shard, or to each node without `--shard-aware`
+    #[clap(long, default_value = "1", env = "CONNECTIONS_PER_SHARD")]
+    pub connections_per_shard: NonZeroUsize,
+
+    /// Compression of the frames sent to the nodes
+    #[clap(long, env = "COMPRESSION")]
+    pub compression: Option<FrameCompression>,
+
+    /// Seconds an insert may take before it fails
+    #[clap(long, default_value = "30", env = "REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+
+    /// Seconds to wait for a connection to a node
+    #[clap(long, default_value = "5", env = "CONNECTION_TIMEOUT")]
+    pub connection_timeout: u64,
+
+    /// Columns bound by each insert: the fields of each row (`row`), every table column leaving absent
+    /// fields unset (`table`), or every table column rejecting rows that miss any (`strict`)
+    #[clap(long, default_value = "row", env = "COLUMN_BINDING")]
+    pub column_binding: ColumnBinding,
+
+    /// Send each row in an insert of its own (`rows`), or the rows of a partition in UNLOGGED batches
+    /// (`unlogged-batches`)
+    #[clap(long, default_value = "rows", env = "WRITE_MODE")]
+    pub write_mode: WriteMode,
+
+    /// Most rows in an UNLOGGED batch
+    #[clap(long, default_value = "100", env = "MAX_BATCH_ROWS")]
+    pub max_batch_rows: usize,
+
+    /// Most bytes of values in an UNLOGGED batch, under Scylla's `batch_size_warn_threshold_in_kb`
+    #[clap(long, default_value = "65536", env = "MAX_BATCH_BYTES")]
+    pub max_batch_bytes: usize,
+
+    /// Most inserts in flight in total, over all batches and connections. Defaults to 128 for each connection
+    /// to the nodes of the local datacenter, counted once at startup
+    #[clap(long, env = "MAX_REQUESTS_IN_FLIGHT")]
+    pub max_requests_in_flight: Option<usize>,
+
+    /// Adapt the inserts in flight to the cluster: start at one per connection, add one per connection every
+    /// second the p99 latency stays under `--target-p99-latency`, and back off on timeouts and overload errors
+    #[clap(long, env = "ADAPTIVE_CONCURRENCY")]
+    pub adaptive_concurrency: bool,
+
+    /// Milliseconds the p99 latency of the inserts should stay under with `--adaptive-concurrency`
+    #[clap(long, default_value = "50", env = "TARGET_P99_LATENCY")]
+    pub target_p99_latency: u64,
+
+    /// Attempts of each write before its rows fail, the first one included
+    #[clap(long, default_value = "5", env = "MAX_ATTEMPTS")]
+    pub max_attempts: u32,
+
+    /// Milliseconds to wait before the first retry, doubled on each following one, with random jitter
+    #[clap(long, default_value = "100", env = "RETRY_INITIAL_BACKOFF")]
+    pub retry_initial_backoff: u64,
+
+    /// Most milliseconds to wait before a retry
+    #[clap(long, default_value = "10000", env = "RETRY_MAX_BACKOFF")]
+    pub retry_max_backoff: u64,
+
+    /// Comma separated classes of errors after which a write is retried
+    #[clap(long, value_delimiter = ',', default_value = "write-timeout,unavailable,overloaded,bootstrapping,request-timeout,connection",
+           env = "RETRY_ON")]
+    pub retry_on: Vec<RetryableError>,
+
     /// Upload Batch size
     #[clap(long, env = "BATCH_SIZE")]
     pub batch_size: u32,
@@ -41,11 +215,55 @@ pub struct CommandLine {
     #[clap(long, env = "CONCURRENT_BATCHES")]
     pub concurrent_batches: usize,
 
+    /// Most rows written per second, over all the batches being inserted
+    #[clap(long, env = "MAX_ROWS_PER_SECOND")]
+    pub max_rows_per_second: Option<u64>,
+
+    /// Most bytes of source records written per second over all the batches being inserted. Records weigh the
+    /// bytes of their text in CSV and JSON files, and their share of the encoded data in Parquet and Avro files
+    #[clap(long, env = "MAX_BYTES_PER_SECOND")]
+    pub max_bytes_per_second: Option<u64>,
+
+    /// File checked every second for `max-rows-per-second = N` and `max-bytes-per-second = N` lines, which
+    /// change the rate limits while loading (`0` or `none` removes a limit)
+    #[clap(long, env = "RATE_CONTROL_FILE")]
+    pub rate_control_file: Option<PathBuf>,
+
+    /// Rows that may fail to convert or write, as a count (`100`) or as a percentage of the rows read (`0.5%`).
+    /// Past a count the run stops at once; a percentage is checked at the end. Exits with code 4 when exceeded.
+    /// Defaults to 0, so that no row is lost silently: the first failing row stops the run. To load the rest,
+    /// raise it (`100%` never stops) and set `--reject-path` to keep the failing rows
+    #[clap(long, default_value = "0", env = "MAX_ERRORS")]
+    pub max_errors: ErrorBudget,
+
+    /// Local path of a JSON lines file, or `s3://` prefix of numbered `part-NNNNN.jsonl` objects, receiving the
+    /// rows that fail, with their source file, line, failing stage and error. On S3, a part is uploaded with every
+    /// checkpoint. Load them again with `--json-pointer /payload` once fixed
+    #[clap(long, env = "REJECT_PATH")]
+    pub reject_path: Option<String>,
+
+    /// Local or `s3://` path of the checkpoint file, where the progress of every source file is saved
+    /// periodically and when the run ends
+    #[clap(long, env = "CHECKPOINT_PATH")]
+    pub checkpoint_path: Option<String>,
+
+    /// Seconds between checkpoint saves
+    #[clap(long, default_value = "60", env = "CHECKPOINT_INTERVAL")]
+    pub checkpoint_interval: u64,
+
+    /// Skip the files and records the checkpoint has as loaded. Safe since inserts are upserts, though rows of
+    /// the batches in flight when the run stopped are written again. Uncompressed CSV and newline delimited
+    /// JSON files continue at the byte offset saved, Parquet files at the row group, other files are read again
+    /// up to the checkpoint
+    #[clap(long, requires = "checkpoint_path", env = "RESUME")]
+    pub resume: bool,
+
     /// The S3 endpoint to connect and save file
     #[clap(long, env = "S3_ENDPOINT")]
     pub s3_endpoint: Option<String>,
 
-    /// S3 Access key
+    /// S3 Access key. Without keys, credentials come from the default AWS chain (environment, profile,
+    /// web identity, container or instance metadata)
     #[clap(long, env = "S3_ACCESS_KEY")]
     pub s3_access_key: Option<String>,
 
@@ -53,7 +271,29 @@ pub struct CommandLine {
     #[clap(long, env = "S3_SECRET_ACCESS_KEY")]
     pub s3_secret_access_key: Option<String>,
 
-    /// S3 Region to connect
-    #[clap(long, default_value="minio", env = "S3_REGION")]
+    /// S3 region, also used to assume `--s3-role-arn`. Defaults to the region of the environment or profile, or
+    /// to `us-east-1` with `--s3-endpoint`
+    #[clap(long, env = "S3_REGION")]
     pub s3_region: Option<String>,
-}
\ No newline at end of file
+
+    /// Address buckets in the path (`endpoint/bucket/key`), as MinIO and most S3-compatible stores need,
+    /// instead of in the host name (`bucket.endpoint/key`)
+    #[clap(long, default_value = "true", action = clap::ArgAction::Set, env = "S3_FORCE_PATH_STYLE")]
+    pub s3_force_path_style: bool,
+
+    /// AWS profile of the default credentials chain, instead of `AWS_PROFILE`
+    #[clap(long, env = "S3_PROFILE")]
+    pub s3_profile: Option<String>,
+
+    /// ARN of a role to assume with the S3 credentials
+    #[clap(long, env = "S3_ROLE_ARN")]
+    pub s3_role_arn: Option<String>,
+}
+
+fn parse_csv_character(value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        value if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!("expected a single ASCII character, found {value:?}")),
+    }
+}
@@ -0,0 +1,9 @@
+use super::row_failure::RowFailure;
+
+/// What came of writing the rows of a batch
+#[derive(Debug, Default)]
+pub struct BatchOutcome {
+    pub failures: Vec<RowFailure>,
+    /// Writes attempted again after a retryable error
+    pub retries: usize,
+}
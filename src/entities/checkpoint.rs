@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+/// Where a record of a text file ends, so that reading can resume right after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePosition {
+    /// Bytes of the decompressed text up to the end of the record
+    pub byte_offset: u64,
+    /// Lines read up to the end of the record, as the reader counts them
+    pub line_number: u64,
+}
+
+/// How far the loading of a source file got
+#[derive(Debug, Clone, Default)]
+pub struct SourceProgress {
+    /// Records from the start of the file already acknowledged by the database
+    pub records: u64,
+    /// Line of the last of those records, to tell where the file was left
+    pub line_number: u64,
+    /// Where the last of those records ends, when the reader of the file tracks it
+    pub position: Option<FilePosition>,
+    pub finished: bool,
+    /// Batches acknowledged while an earlier one was still in flight, by their first record, with their
+    /// record count, last line and end position
+    pending_batches: BTreeMap<u64, (u64, u64, Option<FilePosition>)>,
+}
+
+/// Progress of every source file of a run, saved periodically so an interrupted run can resume
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+    sources: BTreeMap<String, SourceProgress>,
+}
+
+impl Checkpoint {
+    pub fn progress(&self, source_path: &str) -> Option<&SourceProgress> {
+        self.sources.get(source_path)
+    }
+
+    /// Records an inserted batch. Batches complete out of order, so the progress only moves past a batch
+    /// once every batch before it is acknowledged as well.
+    pub fn acknowledge_batch(&mut self, source_path: &str, first_record: u64, records: u64, last_line_number: u64,
+                             end_position: Option<FilePosition>) {
+        let progress = self.sources.entry(source_path.to_owned()).or_default();
+        progress.pending_batches.insert(first_record, (records, last_line_number, end_position));
+
+        while let Some((records, line_number, position)) = progress.pending_batches.remove(&progress.records) {
+            progress.records += records;
+            progress.line_number = line_number;
+            progress.position = position;
+        }
+    }
+
+    pub fn finish_source(&mut self, source_path: &str) {
+        let progress = self.sources.entry(source_path.to_owned()).or_default();
+        progress.finished = true;
+    }
+
+    pub fn to_json(&self) -> String {
+        let sources = self.sources.iter()
+            .map(|(source_path, progress)| {
+                let mut progress_json = json!({"records": progress.records, "line": progress.line_number, "finished": progress.finished});
+                if let Some(position) = progress.position {
+                    progress_json["position"] = json!({"offset": position.byte_offset, "line": position.line_number});
+                }
+                (source_path.clone(), progress_json)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        json!({"sources": sources}).to_string()
+    }
+
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        let document = serde_json::from_str::<Value>(text)?;
+        let sources = document.get("sources").and_then(Value::as_object)
+            .context("Missing the sources object")?;
+
+        let sources = sources.iter()
+            .map(|(source_path, progress)| {
+                let progress = SourceProgress {
+                    records: progress.get("records").and_then(Value::as_u64)
+                        .with_context(|| format!("Missing the records of {source_path}"))?,
+                    line_number: progress.get("line").and_then(Value::as_u64).unwrap_or_default(),
+                    position: progress.get("position").and_then(|position| {
+                        Some(FilePosition {
+                            byte_offset: position.get("offset").and_then(Value::as_u64)?,
+                            line_number: position.get("line").and_then(Value::as_u64)?,
+                        })
+                    }),
+                    finished: progress.get("finished").and_then(Value::as_bool).unwrap_or_default(),
+                    pending_batches: BTreeMap::new(),
+                };
+                anyhow::Ok((source_path.clone(), progress))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Checkpoint { sources })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(byte_offset: u64, line_number: u64) -> Option<FilePosition> {
+        Some(FilePosition { byte_offset, line_number })
+    }
+
+    #[test]
+    fn moves_past_a_batch_once_every_batch_before_it_is_acknowledged() {
+        let mut checkpoint = Checkpoint::default();
+
+        checkpoint.acknowledge_batch("a.csv", 20, 10, 31, position(300, 31));
+        checkpoint.acknowledge_batch("a.csv", 10, 10, 21, position(200, 21));
+        assert!(checkpoint.progress("a.csv").is_some_and(|progress| progress.records == 0 && progress.position.is_none()));
+
+        checkpoint.acknowledge_batch("a.csv", 0, 10, 11, position(100, 11));
+        let progress = checkpoint.progress("a.csv").unwrap();
+        assert_eq!((progress.records, progress.line_number, progress.position), (30, 31, position(300, 31)));
+
+        checkpoint.acknowledge_batch("a.csv", 40, 5, 46, position(450, 46));
+        checkpoint.acknowledge_batch("a.csv", 30, 10, 41, position(400, 41));
+        let progress = checkpoint.progress("a.csv").unwrap();
+        assert_eq!((progress.records, progress.line_number, progress.position), (45, 46, position(450, 46)));
+        assert!(checkpoint.progress("b.csv").is_none());
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.acknowledge_batch("a.csv", 0, 10, 11, position(100, 11));
+        checkpoint.acknowledge_batch("a.csv", 20, 10, 31, position(300, 31));
+        checkpoint.finish_source("a.csv");
+        checkpoint.acknowledge_batch("b.parquet", 0, 50, 50, None);
+
+        let checkpoint = Checkpoint::from_json(&checkpoint.to_json()).unwrap();
+
+        let progress = checkpoint.progress("a.csv").unwrap();
+        assert_eq!((progress.records, progress.line_number, progress.position, progress.finished), (10, 11, position(100, 11), true));
+        let progress = checkpoint.progress("b.parquet").unwrap();
+        assert_eq!((progress.records, progress.line_number, progress.position, progress.finished), (50, 50, None, false));
+    }
+
+    #[test]
+    fn reads_checkpoints_without_positions_and_rejects_invalid_ones() {
+        let checkpoint = Checkpoint::from_json(r#"{"sources": {"a.json": {"records": 7}}}"#).unwrap();
+        let progress = checkpoint.progress("a.json").unwrap();
+        assert_eq!((progress.records, progress.line_number, progress.position, progress.finished), (7, 0, None, false));
+
+        assert!(Checkpoint::from_json(r#"{"sources": {"a.json": {"line": 7}}}"#).is_err());
+        assert!(Checkpoint::from_json(r#"{"files": {}}"#).is_err());
+        assert!(Checkpoint::from_json("not json").is_err());
+    }
+}
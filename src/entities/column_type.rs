@@ -0,0 +1,180 @@
+use std::{fmt::Display, str::FromStr};
+
+/// CQL type of a table column, as described by the `type` field of `system_schema.columns`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    Ascii,
+    BigInt,
+    Blob,
+    Boolean,
+    Counter,
+    Date,
+    Decimal,
+    Double,
+    Duration,
+    Float,
+    Inet,
+    Int,
+    SmallInt,
+    Text,
+    Time,
+    Timestamp,
+    Timeuuid,
+    TinyInt,
+    Uuid,
+    Varint,
+    List(Box<ColumnType>),
+    Set(Box<ColumnType>),
+    Map(Box<ColumnType>, Box<ColumnType>),
+    Tuple(Vec<ColumnType>),
+    UserDefined(String),
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(type_string: &str) -> Result<Self, Self::Err> {
+        let type_string = type_string.trim();
+
+        let Some((name, arguments)) = type_string.split_once('<') else {
+            return Ok(parse_native_type(type_string));
+        };
+
+        let Some(arguments) = arguments.strip_suffix('>') else {
+            anyhow::bail!("Invalid column type: {type_string}")
+        };
+
+        let arguments = split_type_arguments(arguments)?;
+
+        let column_type = match (name.trim(), &arguments[..]) {
+            ("frozen", [inner_type]) => inner_type.parse()?,
+            ("list", [element_type]) => ColumnType::List(Box::new(element_type.parse()?)),
+            ("set", [element_type]) => ColumnType::Set(Box::new(element_type.parse()?)),
+            ("map", [key_type, value_type]) => ColumnType::Map(Box::new(key_type.parse()?), Box::new(value_type.parse()?)),
+            ("tuple", element_types) => {
+                let element_types = element_types.iter().map(|element_type| element_type.parse()).collect::<anyhow::Result<Vec<_>>>()?;
+                ColumnType::Tuple(element_types)
+            },
+            _ => anyhow::bail!("Invalid column type: {type_string}"),
+        };
+
+        Ok(column_type)
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnType::Ascii => write!(formatter, "ascii"),
+            ColumnType::BigInt => write!(formatter, "bigint"),
+            ColumnType::Blob => write!(formatter, "blob"),
+            ColumnType::Boolean => write!(formatter, "boolean"),
+            ColumnType::Counter => write!(formatter, "counter"),
+            ColumnType::Date => write!(formatter, "date"),
+            ColumnType::Decimal => write!(formatter, "decimal"),
+            ColumnType::Double => write!(formatter, "double"),
+            ColumnType::Duration => write!(formatter, "duration"),
+            ColumnType::Float => write!(formatter, "float"),
+            ColumnType::Inet => write!(formatter, "inet"),
+            ColumnType::Int => write!(formatter, "int"),
+            ColumnType::SmallInt => write!(formatter, "smallint"),
+            ColumnType::Text => write!(formatter, "text"),
+            ColumnType::Time => write!(formatter, "time"),
+            ColumnType::Timestamp => write!(formatter, "timestamp"),
+            ColumnType::Timeuuid => write!(formatter, "timeuuid"),
+            ColumnType::TinyInt => write!(formatter, "tinyint"),
+            ColumnType::Uuid => write!(formatter, "uuid"),
+            ColumnType::Varint => write!(formatter, "varint"),
+            ColumnType::List(element_type) => write!(formatter, "list<{element_type}>"),
+            ColumnType::Set(element_type) => write!(formatter, "set<{element_type}>"),
+            ColumnType::Map(key_type, value_type) => write!(formatter, "map<{key_type}, {value_type}>"),
+            ColumnType::Tuple(element_types) => {
+                let element_types = element_types.iter().map(|element_type| element_type.to_string()).collect::<Vec<_>>().join(", ");
+                write!(formatter, "tuple<{element_types}>")
+            },
+            ColumnType::UserDefined(name) => write!(formatter, "{name}"),
+        }
+    }
+}
+
+
+fn parse_native_type(type_name: &str) -> ColumnType {
+    match type_name {
+        "ascii" => ColumnType::Ascii,
+        "bigint" => ColumnType::BigInt,
+        "blob" => ColumnType::Blob,
+        "boolean" => ColumnType::Boolean,
+        "counter" => ColumnType::Counter,
+        "date" => ColumnType::Date,
+        "decimal" => ColumnType::Decimal,
+        "double" => ColumnType::Double,
+        "duration" => ColumnType::Duration,
+        "float" => ColumnType::Float,
+        "inet" => ColumnType::Inet,
+        "int" => ColumnType::Int,
+        "smallint" => ColumnType::SmallInt,
+        "text" | "varchar" => ColumnType::Text,
+        "time" => ColumnType::Time,
+        "timestamp" => ColumnType::Timestamp,
+        "timeuuid" => ColumnType::Timeuuid,
+        "tinyint" => ColumnType::TinyInt,
+        "uuid" => ColumnType::Uuid,
+        "varint" => ColumnType::Varint,
+        user_defined_type => ColumnType::UserDefined(user_defined_type.to_owned()),
+    }
+}
+
+/// Splits the arguments of a parametrized type (`text, frozen<map<int, text>>`) on its top-level commas
+fn split_type_arguments(arguments: &str) -> anyhow::Result<Vec<&str>> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut type_arguments = Vec::new();
+
+    for (index, character) in arguments.char_indices() {
+        match character {
+            '<' => depth += 1,
+            '>' if depth == 0 => anyhow::bail!("Unbalanced type arguments: {arguments}"),
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                type_arguments.push(arguments[start..index].trim());
+                start = index + 1;
+            },
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        anyhow::bail!("Unbalanced type arguments: {arguments}")
+    }
+
+    type_arguments.push(arguments[start..].trim());
+
+    Ok(type_arguments)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_types() {
+        let column_type: ColumnType = "frozen<map<text, list<int>>>".parse().unwrap();
+        assert_eq!(column_type, ColumnType::Map(Box::new(ColumnType::Text), Box::new(ColumnType::List(Box::new(ColumnType::Int)))));
+
+        let column_type: ColumnType = "tuple<varchar, frozen<set<frozen<tuple<int, address>>>>, map<int, text>>".parse().unwrap();
+        assert_eq!(column_type, ColumnType::Tuple(vec![
+            ColumnType::Text,
+            ColumnType::Set(Box::new(ColumnType::Tuple(vec![ColumnType::Int, ColumnType::UserDefined("address".to_owned())]))),
+            ColumnType::Map(Box::new(ColumnType::Int), Box::new(ColumnType::Text)),
+        ]));
+        assert_eq!(column_type.to_string(), "tuple<text, set<tuple<int, address>>, map<int, text>>");
+    }
+
+    #[test]
+    fn rejects_malformed_types() {
+        for type_string in ["list<int", "map<int>", "list<int, text>", "map<int, list<text>", "map<int, text>>", "vector<float, 3>"] {
+            assert!(type_string.parse::<ColumnType>().is_err(), "{type_string}");
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use scylla::frame::response::result::CqlValue;
+
+use super::{ColumnType, data_value::parse_text};
+
+/// Parses collection and tuple values written as text, in CQL (`{'a', 'b'}`, `['x']`, `{'k': 1}`, `(1, 'a')`)
+/// or JSON (`["a", "b"]`, `{"k": 1}`) syntax
+pub fn parse_collection_literal(text: &str, column_type: &ColumnType) -> anyhow::Result<CqlValue> {
+    let mut parser = LiteralParser { text, position: 0 };
+    let value = parser.parse_value(column_type)?;
+
+    parser.skip_whitespaces();
+    if parser.position != text.len() {
+        anyhow::bail!("Unexpected characters at position {} of {text:?}", parser.position)
+    }
+
+    Ok(value)
+}
+
+
+/// Characters ending an unquoted value, and an unquoted map key
+const VALUE_ENDS: &str = ",)]}";
+const MAP_KEY_ENDS: &str = ",:)]}";
+
+struct LiteralParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> LiteralParser<'a> {
+
+    fn parse_value(&mut self, column_type: &ColumnType) -> anyhow::Result<CqlValue> {
+        self.parse_value_ending(column_type, VALUE_ENDS)
+    }
+
+    /// Parses a value, which ends at one of `ends` when it is an unquoted scalar
+    fn parse_value_ending(&mut self, column_type: &ColumnType, ends: &str) -> anyhow::Result<CqlValue> {
+        match column_type {
+            ColumnType::List(element_type) => {
+                let closing = self.expect_opening(&['[', '{'])?;
+                Ok(CqlValue::List(self.parse_elements(closing, element_type)?))
+            },
+            ColumnType::Set(element_type) => {
+                let closing = self.expect_opening(&['{', '['])?;
+                Ok(CqlValue::Set(self.parse_elements(closing, element_type)?))
+            },
+            ColumnType::Map(key_type, value_type) => {
+                let closing = self.expect_opening(&['{'])?;
+                let mut entries = Vec::new();
+
+                while !self.consume_closing(closing, entries.is_empty())? {
+                    let key = self.parse_value_ending(key_type, MAP_KEY_ENDS)?;
+                    self.expect(':')?;
+                    let value = self.parse_value(value_type)?;
+                    entries.push((key, value));
+                }
+
+                Ok(CqlValue::Map(entries))
+            },
+            ColumnType::Tuple(element_types) => {
+                let closing = self.expect_opening(&['(', '['])?;
+                let mut elements = Vec::new();
+
+                while !self.consume_closing(closing, elements.is_empty())? {
+                    let Some(element_type) = element_types.get(elements.len()) else {
+                        anyhow::bail!("Too many elements for {column_type} in {:?}", self.text)
+                    };
+
+                    let element = match self.parse_token(VALUE_ENDS)? {
+                        Token::Null => None,
+                        Token::Quoted(token) | Token::Unquoted(token) => Some(parse_text(&token, element_type)?),
+                        Token::Nested => Some(self.parse_value(element_type)?),
+                    };
+                    elements.push(element);
+                }
+
+                if elements.len() != element_types.len() {
+                    anyhow::bail!("Expected {} tuple elements for {column_type}, found {}", element_types.len(), elements.len())
+                }
+
+                Ok(CqlValue::Tuple(elements))
+            },
+            scalar_type => match self.parse_token(ends)? {
+                Token::Quoted(token) => parse_text(&token, scalar_type),
+                // `{12:30:00: 1}` would read as the key 12, so keys that may hold a ':' must be quoted
+                Token::Unquoted(token) if ends == MAP_KEY_ENDS && matches!(scalar_type, ColumnType::Time | ColumnType::Timestamp | ColumnType::Inet) => {
+                    let remaining = &self.text[self.position..];
+                    if token.is_empty() || remaining.starts_with(':') && !remaining[1..].starts_with(char::is_whitespace) {
+                        anyhow::bail!("Quote the {scalar_type} map keys of {:?}, unquoted keys end at the first ':'", self.text)
+                    }
+                    parse_text(&token, scalar_type)
+                },
+                Token::Unquoted(token) => parse_text(&token, scalar_type),
+                Token::Null => anyhow::bail!("Cannot convert null to {scalar_type}"),
+                Token::Nested => anyhow::bail!("Cannot convert a collection to {scalar_type}"),
+            },
+        }
+    }
+
+    fn parse_elements(&mut self, closing: char, element_type: &ColumnType) -> anyhow::Result<Vec<CqlValue>> {
+        let mut elements = Vec::new();
+
+        while !self.consume_closing(closing, elements.is_empty())? {
+            elements.push(self.parse_value(element_type)?);
+        }
+
+        Ok(elements)
+    }
+
+    /// Reads a scalar token, unquoted ones ending at one of `ends`, leaving the position untouched when a nested
+    /// collection starts
+    fn parse_token(&mut self, ends: &str) -> anyhow::Result<Token> {
+        self.skip_whitespaces();
+
+        let remaining = &self.text[self.position..];
+
+        match remaining.chars().next() {
+            None => anyhow::bail!("Unexpected end of {:?}", self.text),
+            Some('[' | '{' | '(') => Ok(Token::Nested),
+            Some('\'') => {
+                let mut token = String::new();
+                let mut characters = remaining.char_indices().skip(1).peekable();
+
+                while let Some((index, character)) = characters.next() {
+                    if character != '\'' {
+                        token.push(character);
+                    } else if let Some((_, '\'')) = characters.peek() {
+                        token.push('\'');
+                        characters.next();
+                    } else {
+                        self.position += index + 1;
+                        return Ok(Token::Quoted(token));
+                    }
+                }
+
+                anyhow::bail!("Unterminated quoted string in {:?}", self.text)
+            },
+            Some('"') => {
+                let mut stream = serde_json::Deserializer::from_str(remaining).into_iter::<String>();
+                let token = stream.next()
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated quoted string in {:?}", self.text))??;
+                self.position += stream.byte_offset();
+                Ok(Token::Quoted(token))
+            },
+            Some(_) => {
+                let length = remaining.find(|character: char| ends.contains(character)).unwrap_or(remaining.len());
+                let token = remaining[..length].trim();
+                self.position += length;
+
+                if token.eq_ignore_ascii_case("null") {
+                    Ok(Token::Null)
+                } else {
+                    Ok(Token::Unquoted(token.to_owned()))
+                }
+            },
+        }
+    }
+
+    fn expect_opening(&mut self, openings: &[char]) -> anyhow::Result<char> {
+        self.skip_whitespaces();
+
+        let closing = match self.text[self.position..].chars().next() {
+            Some('[') if openings.contains(&'[') => ']',
+            Some('{') if openings.contains(&'{') => '}',
+            Some('(') if openings.contains(&'(') => ')',
+            _ => anyhow::bail!("Expected one of {openings:?} at position {} of {:?}", self.position, self.text),
+        };
+
+        self.position += 1;
+        Ok(closing)
+    }
+
+    /// Consumes the closing character, or the separator before the next element when the collection continues
+    fn consume_closing(&mut self, closing: char, first_element: bool) -> anyhow::Result<bool> {
+        self.skip_whitespaces();
+
+        if self.text[self.position..].starts_with(closing) {
+            self.position += 1;
+            return Ok(true);
+        }
+
+        if !first_element {
+            self.expect(',')?;
+        }
+
+        Ok(false)
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        self.skip_whitespaces();
+
+        if !self.text[self.position..].starts_with(expected) {
+            anyhow::bail!("Expected '{expected}' at position {} of {:?}", self.position, self.text)
+        }
+
+        self.position += expected.len_utf8();
+        Ok(())
+    }
+
+    fn skip_whitespaces(&mut self) {
+        let remaining = &self.text[self.position..];
+        self.position += remaining.len() - remaining.trim_start().len();
+    }
+}
+
+enum Token {
+    Quoted(String),
+    Unquoted(String),
+    Null,
+    Nested,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, column_type: &str) -> anyhow::Result<CqlValue> {
+        parse_collection_literal(text, &column_type.parse()?)
+    }
+
+    #[test]
+    fn parses_unquoted_timestamps_and_times_in_lists_and_sets() {
+        let Ok(CqlValue::List(timestamps)) = parse("[2024-01-01T10:00:00Z, 2024-01-02T10:00:00Z]", "list<timestamp>") else {
+            panic!("Expected a list")
+        };
+        assert_eq!(timestamps.len(), 2);
+        assert!(matches!(timestamps[0], CqlValue::Timestamp(_)));
+
+        let Ok(CqlValue::Set(times)) = parse("{12:30:00}", "set<time>") else {
+            panic!("Expected a set")
+        };
+        assert!(matches!(times[..], [CqlValue::Time(_)]));
+    }
+
+    #[test]
+    fn ends_unquoted_map_keys_at_colons() {
+        let map = parse("{1: 'one', 2: 'two'}", "map<int, text>").unwrap();
+        assert_eq!(map, CqlValue::Map(vec![
+            (CqlValue::Int(1), CqlValue::Text("one".to_owned())),
+            (CqlValue::Int(2), CqlValue::Text("two".to_owned())),
+        ]));
+
+        let Ok(CqlValue::Map(entries)) = parse("{'12:30:00': 12:45:00}", "map<time, time>") else {
+            panic!("Expected a map")
+        };
+        assert!(matches!(entries[..], [(CqlValue::Time(_), CqlValue::Time(_))]));
+    }
+
+    #[test]
+    fn asks_to_quote_time_map_keys() {
+        let error = parse("{12:30:00: 1}", "map<time, int>").unwrap_err();
+        assert!(error.to_string().starts_with("Quote the time map keys"), "{error:?}");
+
+        let Ok(CqlValue::Map(entries)) = parse("{45000000000: 1}", "map<time, int>") else {
+            panic!("Expected a map")
+        };
+        assert!(matches!(entries[..], [(CqlValue::Time(_), CqlValue::Int(1))]));
+    }
+}
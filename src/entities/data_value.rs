@@ -1,58 +1,42 @@
-use std::collections::HashMap;
-use scylla::_macro_internal::Value as ScylaValue;
-use scylla::_macro_internal::ValueTooBig;
-use scylla::frame::value::Unset;
+use std::{collections::HashMap, net::IpAddr, str::FromStr, sync::LazyLock};
+use anyhow::Context;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use num_bigint::BigInt;
+use regex::Regex;
+use scylla::frame::response::result::CqlValue;
+use scylla::frame::value::{CqlDuration, MaybeUnset};
 use serde_json::Value as SerdeValue;
+use uuid::Uuid;
+
+use super::{ColumnType, TableSchema, cql_literal::parse_collection_literal};
+
+/// Values bound by name to the placeholders of a prepared statement
+pub type NamedValues = HashMap<String, MaybeUnset<CqlValue>>;
 
 pub struct DataValue(SerdeValue);
 
 impl DataValue {
     pub fn new(serde_value: SerdeValue) -> DataValue { DataValue(serde_value) }
-}
 
+    /// Converts a row into values typed after the columns of `table_schema`.
+    /// Null fields are left unset, so they do not write tombstones.
+    pub fn into_named_values(self, table_schema: &TableSchema) -> anyhow::Result<NamedValues> {
+        let SerdeValue::Object(fields) = self.0 else {
+            anyhow::bail!("Expected a JSON object, found {}", self.0)
+        };
 
-impl ScylaValue for DataValue {
-    fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ValueTooBig> {
-        let json_value = self.into();
+        fields.into_iter().map(|(name, value)| {
+            let column_type = table_schema.column_type(&name)
+                .with_context(|| format!("Column `{name}` does not exist in the target table"))?;
 
-        match json_value {
-            SerdeValue::String(string_value) => {
-                string_value.serialize(buf)?;
-            }
-            SerdeValue::Null => {
-                let unset = Unset;
-                unset.serialize(buf)?;
-            },
-            SerdeValue::Bool(bool_value) => {
-                bool_value.serialize(buf)?;
-            },
-            SerdeValue::Number(value_number) => {               
-                if value_number.is_u64() {
-                    let value = value_number.as_u64().unwrap() as i64;
-                    value.serialize(buf)?;
-                } else if value_number.is_i64() {
-                    let value = value_number.as_i64().unwrap();
-                    value.serialize(buf)?;
-                } else if value_number.is_f64() {
-                    let value = value_number.as_f64().unwrap();
-                    value.serialize(buf)?;
-                }
-            },
-            SerdeValue::Array(value_array) => {
-                let data_value_array: Vec<_> = value_array.iter().map(|item| DataValue(item.clone())).collect();
-                data_value_array.serialize(buf)?;
-            },
-            SerdeValue::Object(value_map) => {
-                let hash_map: HashMap<String, DataValue> = value_map.iter().map(|(key, value)| {
-                    let data_value = DataValue(value.to_owned());
-                    (key.to_owned(), data_value)
-                }).collect();
-                
-                hash_map.serialize(buf)?;
-            },
-        };
-        
-        Ok(())
+            let cql_value = match value {
+                SerdeValue::Null => MaybeUnset::Unset,
+                value => MaybeUnset::Set(to_cql_value(&value, column_type).with_context(|| format!("Column `{name}`"))?),
+            };
+
+            Ok((name, cql_value))
+        }).collect()
     }
 }
 
@@ -62,26 +46,338 @@ impl From<serde_json::Value> for DataValue {
     }
 }
 
-impl Into<serde_json::Value> for &DataValue {
-    fn into(self) -> serde_json::Value {
-        self.0.clone()
+
+fn to_cql_value(value: &SerdeValue, column_type: &ColumnType) -> anyhow::Result<CqlValue> {
+    let cql_value = match (column_type, value) {
+        (ColumnType::Counter, _) => anyhow::bail!("Counter columns cannot be written with INSERT"),
+        (ColumnType::UserDefined(type_name), _) => anyhow::bail!("User defined type {type_name} is not supported"),
+        (_, SerdeValue::Null) => anyhow::bail!("Cannot convert null to {column_type}"),
+
+        (ColumnType::Text, SerdeValue::Number(_) | SerdeValue::Bool(_)) => CqlValue::Text(value.to_string()),
+        (ColumnType::Boolean, SerdeValue::Bool(bool_value)) => CqlValue::Boolean(*bool_value),
+        (ColumnType::TinyInt, SerdeValue::Number(number)) => CqlValue::TinyInt(integer_from_number(number, column_type)?),
+        (ColumnType::SmallInt, SerdeValue::Number(number)) => CqlValue::SmallInt(integer_from_number(number, column_type)?),
+        (ColumnType::Int, SerdeValue::Number(number)) => CqlValue::Int(integer_from_number(number, column_type)?),
+        (ColumnType::BigInt, SerdeValue::Number(number)) => CqlValue::BigInt(integer_from_number(number, column_type)?),
+        (ColumnType::Float, SerdeValue::Number(number)) => CqlValue::Float(float_from_number(number, column_type)? as f32),
+        (ColumnType::Double, SerdeValue::Number(number)) => CqlValue::Double(float_from_number(number, column_type)?),
+        (ColumnType::Timestamp, SerdeValue::Number(number)) => CqlValue::Timestamp(chrono::Duration::milliseconds(integer_from_number(number, column_type)?)),
+        (ColumnType::Date, SerdeValue::Number(number)) => CqlValue::Date(date_from_days(integer_from_number(number, column_type)?)?),
+        (ColumnType::Time, SerdeValue::Number(number)) => CqlValue::Time(chrono::Duration::nanoseconds(integer_from_number(number, column_type)?)),
+        (ColumnType::Decimal | ColumnType::Varint, SerdeValue::Number(number)) => parse_text(&number.to_string(), column_type)?,
+
+        (ColumnType::List(element_type), SerdeValue::Array(elements)) => CqlValue::List(to_cql_values(elements, element_type)?),
+        (ColumnType::Set(element_type), SerdeValue::Array(elements)) => CqlValue::Set(to_cql_values(elements, element_type)?),
+        (ColumnType::Map(key_type, value_type), SerdeValue::Object(entries)) => {
+            let entries = entries.iter().map(|(key, value)| {
+                let key = parse_text(key, key_type).with_context(|| format!("Map key {key:?}"))?;
+                let value = to_cql_value(value, value_type).with_context(|| format!("Map value {value}"))?;
+                Ok((key, value))
+            }).collect::<anyhow::Result<Vec<_>>>()?;
+
+            CqlValue::Map(entries)
+        },
+        (ColumnType::Tuple(element_types), SerdeValue::Array(elements)) => {
+            if elements.len() != element_types.len() {
+                anyhow::bail!("Expected {} tuple elements for {column_type}, found {}", element_types.len(), elements.len())
+            }
+
+            let elements = elements.iter().zip(element_types).map(|(element, element_type)| {
+                match element {
+                    SerdeValue::Null => Ok(None),
+                    element => to_cql_value(element, element_type).map(Some),
+                }
+            }).collect::<anyhow::Result<Vec<_>>>()?;
+
+            CqlValue::Tuple(elements)
+        },
+
+        (ColumnType::List(_) | ColumnType::Set(_) | ColumnType::Map(_, _) | ColumnType::Tuple(_), SerdeValue::String(text)) =>
+            parse_collection_literal(text, column_type)?,
+        (_, SerdeValue::String(text)) => parse_text(text, column_type)?,
+        _ => anyhow::bail!("Cannot convert {value} to {column_type}"),
+    };
+
+    Ok(cql_value)
+}
+
+fn to_cql_values(elements: &[SerdeValue], element_type: &ColumnType) -> anyhow::Result<Vec<CqlValue>> {
+    elements.iter().map(|element| to_cql_value(element, element_type)).collect()
+}
+
+/// Parses the textual representation of a scalar CQL value
+pub(super) fn parse_text(text: &str, column_type: &ColumnType) -> anyhow::Result<CqlValue> {
+    let cql_value = match column_type {
+        ColumnType::Text => CqlValue::Text(text.to_owned()),
+        ColumnType::Ascii if text.is_ascii() => CqlValue::Ascii(text.to_owned()),
+        ColumnType::Boolean => CqlValue::Boolean(parse_boolean(text)?),
+        ColumnType::TinyInt => CqlValue::TinyInt(parse_scalar(text, column_type)?),
+        ColumnType::SmallInt => CqlValue::SmallInt(parse_scalar(text, column_type)?),
+        ColumnType::Int => CqlValue::Int(parse_scalar(text, column_type)?),
+        ColumnType::BigInt => CqlValue::BigInt(parse_scalar(text, column_type)?),
+        ColumnType::Float => CqlValue::Float(parse_scalar(text, column_type)?),
+        ColumnType::Double => CqlValue::Double(parse_scalar(text, column_type)?),
+        ColumnType::Decimal => CqlValue::Decimal(parse_scalar::<BigDecimal>(text, column_type)?),
+        ColumnType::Varint => CqlValue::Varint(parse_scalar::<BigInt>(text, column_type)?),
+        ColumnType::Uuid => CqlValue::Uuid(parse_scalar(text, column_type)?),
+        ColumnType::Timeuuid => {
+            let uuid: Uuid = parse_scalar(text, column_type)?;
+            if uuid.get_version_num() != 1 {
+                anyhow::bail!("Cannot convert {text:?} to timeuuid: not a version 1 UUID")
+            }
+            CqlValue::Timeuuid(uuid)
+        },
+        ColumnType::Inet => CqlValue::Inet(parse_scalar::<IpAddr>(text, column_type)?),
+        ColumnType::Timestamp => CqlValue::Timestamp(parse_timestamp(text)?),
+        ColumnType::Date => CqlValue::Date(parse_date(text)?),
+        ColumnType::Time => CqlValue::Time(parse_time(text)?),
+        ColumnType::Blob => CqlValue::Blob(parse_blob(text)?),
+        ColumnType::Duration => CqlValue::Duration(parse_duration(text)?),
+        _ => anyhow::bail!("Cannot convert {text:?} to {column_type}"),
+    };
+
+    Ok(cql_value)
+}
+
+fn parse_scalar<T: FromStr>(text: &str, column_type: &ColumnType) -> anyhow::Result<T> {
+    text.trim().parse().map_err(|_| anyhow::anyhow!("Cannot convert {text:?} to {column_type}"))
+}
+
+fn parse_boolean(text: &str) -> anyhow::Result<bool> {
+    match text.trim().to_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "1" => Ok(true),
+        "false" | "f" | "no" | "n" | "0" => Ok(false),
+        _ => anyhow::bail!("Cannot convert {text:?} to boolean"),
     }
 }
 
+fn integer_from_number<T: TryFrom<i64>>(number: &serde_json::Number, column_type: &ColumnType) -> anyhow::Result<T> {
+    number.as_i64()
+        .and_then(|integer| T::try_from(integer).ok())
+        .with_context(|| format!("Cannot convert {number} to {column_type}: not an integer in range"))
+}
 
-impl Into<HashMap<String, DataValue>> for DataValue {
+fn float_from_number(number: &serde_json::Number, column_type: &ColumnType) -> anyhow::Result<f64> {
+    number.as_f64().with_context(|| format!("Cannot convert {number} to {column_type}"))
+}
 
-    fn into(self) -> HashMap<String, DataValue> {
-        let mut serialized_value = HashMap::new();
+/// Accepts epoch milliseconds, RFC 3339 and the `yyyy-mm-dd[ hh:mm:ss[.fff]][+zzzz]` forms accepted by CQL
+fn parse_timestamp(text: &str) -> anyhow::Result<chrono::Duration> {
+    let text = text.trim();
 
-        if let Some(serde_json_map) = self.0.as_object() {
-            for (name, value) in serde_json_map {
-                let data_value = DataValue(value.to_owned());
-                serialized_value.insert(name.to_owned(), data_value);
-            }
+    if let Ok(milliseconds) = text.parse::<i64>() {
+        return Ok(chrono::Duration::milliseconds(milliseconds));
+    }
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(chrono::Duration::milliseconds(date_time.timestamp_millis()));
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S%.f%z", "%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M%z"] {
+        if let Ok(date_time) = DateTime::parse_from_str(text, format) {
+            return Ok(chrono::Duration::milliseconds(date_time.timestamp_millis()));
         }
+    }
 
-        serialized_value
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M"] {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(chrono::Duration::milliseconds(date_time.and_utc().timestamp_millis()));
+        }
     }
+
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(chrono::Duration::milliseconds(date.and_time(NaiveTime::MIN).and_utc().timestamp_millis()));
+    }
+
+    anyhow::bail!("Cannot convert {text:?} to timestamp")
+}
+
+/// Accepts `yyyy-mm-dd` dates or a number of days since the unix epoch
+fn parse_date(text: &str) -> anyhow::Result<u32> {
+    let text = text.trim();
+
+    if let Ok(days) = text.parse::<i64>() {
+        return date_from_days(days);
+    }
+
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .with_context(|| format!("Cannot convert {text:?} to date"))?;
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+
+    date_from_days((date - epoch).num_days())
+}
+
+/// CQL dates are days since the unix epoch, centered at 2^31
+fn date_from_days(days: i64) -> anyhow::Result<u32> {
+    u32::try_from(days + (1 << 31)).with_context(|| format!("Date {days} days from epoch is out of range"))
 }
 
+/// Accepts `hh:mm:ss[.fffffffff]` or a number of nanoseconds since midnight
+fn parse_time(text: &str) -> anyhow::Result<chrono::Duration> {
+    let text = text.trim();
+
+    if let Ok(nanoseconds) = text.parse::<i64>() {
+        return Ok(chrono::Duration::nanoseconds(nanoseconds));
+    }
+
+    let time = NaiveTime::parse_from_str(text, "%H:%M:%S%.f")
+        .with_context(|| format!("Cannot convert {text:?} to time"))?;
+    let nanoseconds = i64::from(time.num_seconds_from_midnight()) * 1_000_000_000 + i64::from(time.nanosecond());
+
+    Ok(chrono::Duration::nanoseconds(nanoseconds))
+}
+
+/// Accepts hexadecimal strings, with or without the `0x` prefix
+fn parse_blob(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text.trim();
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        anyhow::bail!("Cannot convert {text:?} to blob: expected an hexadecimal string")
+    }
+
+    (0..hex.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Cannot convert {text:?} to blob: expected an hexadecimal string"))
+}
+
+static DURATION_UNIT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(\d+)(mo|ms|us|µs|ns|y|w|d|h|m|s)").unwrap());
+
+/// Accepts the CQL duration format, like `1y2mo3w4d5h6m7s8ms9us10ns`
+fn parse_duration(text: &str) -> anyhow::Result<CqlDuration> {
+    let text = text.trim();
+    let (negative, units) = match text.strip_prefix('-') {
+        Some(units) => (true, units),
+        None => (false, text),
+    };
+
+    let mut duration = CqlDuration { months: 0, days: 0, nanoseconds: 0 };
+    let mut parsed_length = 0;
+
+    for captures in DURATION_UNIT_REGEX.captures_iter(units) {
+        let amount: i64 = captures[1].parse()?;
+        parsed_length += captures[0].len();
+
+        match captures[2].to_lowercase().as_str() {
+            "y" => duration.months += i32::try_from(amount * 12)?,
+            "mo" => duration.months += i32::try_from(amount)?,
+            "w" => duration.days += i32::try_from(amount * 7)?,
+            "d" => duration.days += i32::try_from(amount)?,
+            "h" => duration.nanoseconds += amount * 3_600_000_000_000,
+            "m" => duration.nanoseconds += amount * 60_000_000_000,
+            "s" => duration.nanoseconds += amount * 1_000_000_000,
+            "ms" => duration.nanoseconds += amount * 1_000_000,
+            "us" | "µs" => duration.nanoseconds += amount * 1_000,
+            _ => duration.nanoseconds += amount,
+        }
+    }
+
+    if units.is_empty() || parsed_length != units.len() {
+        anyhow::bail!("Cannot convert {text:?} to duration")
+    }
+
+    if negative {
+        duration = CqlDuration { months: -duration.months, days: -duration.days, nanoseconds: -duration.nanoseconds };
+    }
+
+    Ok(duration)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(text: &str, column_type: &str) -> anyhow::Result<CqlValue> {
+        parse_text(text, &column_type.parse()?)
+    }
+
+    fn milliseconds(milliseconds: i64) -> CqlValue {
+        CqlValue::Timestamp(chrono::Duration::milliseconds(milliseconds))
+    }
+
+    #[test]
+    fn parses_timestamps_in_every_accepted_format() {
+        let expected = milliseconds(1_704_103_200_500);
+
+        for text in ["1704103200500", "2024-01-01T10:00:00.5Z", "2024-01-01T12:00:00.500+02:00", "2024-01-01 12:00:00.5+0200",
+                     "2024-01-01 10:00:00.500", "2024-01-01T10:00:00.5"] {
+            assert_eq!(parse(text, "timestamp").unwrap(), expected, "{text}");
+        }
+
+        assert_eq!(parse("2024-01-01 10:00", "timestamp").unwrap(), milliseconds(1_704_103_200_000));
+        assert_eq!(parse("2024-01-01", "timestamp").unwrap(), milliseconds(1_704_067_200_000));
+        assert!(parse("01/01/2024", "timestamp").is_err());
+    }
+
+    #[test]
+    fn parses_dates_as_days_centered_at_2_to_the_31() {
+        assert_eq!(parse("1970-01-01", "date").unwrap(), CqlValue::Date(1 << 31));
+        assert_eq!(parse("1969-12-31", "date").unwrap(), CqlValue::Date((1 << 31) - 1));
+        assert_eq!(parse("19723", "date").unwrap(), CqlValue::Date((1 << 31) + 19723));
+        assert_eq!(parse("2024-01-01", "date").unwrap(), CqlValue::Date((1 << 31) + 19723));
+        assert!(parse("-2147483649", "date").is_err());
+        assert!(parse("2024-13-01", "date").is_err());
+    }
+
+    #[test]
+    fn parses_times_as_nanoseconds_since_midnight() {
+        assert_eq!(parse("01:02:03", "time").unwrap(), CqlValue::Time(chrono::Duration::nanoseconds(3_723_000_000_000)));
+        assert_eq!(parse("00:00:00.000000123", "time").unwrap(), CqlValue::Time(chrono::Duration::nanoseconds(123)));
+        assert_eq!(parse("45000", "time").unwrap(), CqlValue::Time(chrono::Duration::nanoseconds(45000)));
+        assert!(parse("25:00:00", "time").is_err());
+    }
+
+    #[test]
+    fn parses_hexadecimal_blobs() {
+        assert_eq!(parse("0xCAFE01", "blob").unwrap(), CqlValue::Blob(vec![0xca, 0xfe, 0x01]));
+        assert_eq!(parse("cafe01", "blob").unwrap(), CqlValue::Blob(vec![0xca, 0xfe, 0x01]));
+        assert_eq!(parse("0x", "blob").unwrap(), CqlValue::Blob(vec![]));
+        assert!(parse("0xcaf", "blob").is_err());
+        assert!(parse("0xcafg", "blob").is_err());
+        assert!(parse("0xcaé", "blob").is_err());
+    }
+
+    #[test]
+    fn parses_durations_in_the_cql_format() {
+        let duration = |months, days, nanoseconds| CqlValue::Duration(CqlDuration { months, days, nanoseconds });
+
+        assert_eq!(parse("1y2mo3w4d5h6m7s8ms9us10ns", "duration").unwrap(),
+                   duration(14, 25, 5 * 3_600_000_000_000 + 6 * 60_000_000_000 + 7_008_009_010));
+        assert_eq!(parse("-1d12h", "duration").unwrap(), duration(0, -1, -43_200_000_000_000));
+        assert_eq!(parse("90M", "duration").unwrap(), duration(0, 0, 5_400_000_000_000));
+        assert_eq!(parse("3µs", "duration").unwrap(), duration(0, 0, 3_000));
+        assert!(parse("", "duration").is_err());
+        assert!(parse("1d 2h", "duration").is_err());
+        assert!(parse("P1D", "duration").is_err());
+    }
+
+    #[test]
+    fn checks_the_range_of_integers() {
+        assert_eq!(parse("127", "tinyint").unwrap(), CqlValue::TinyInt(127));
+        assert!(parse("128", "tinyint").is_err());
+        assert_eq!(parse("-32768", "smallint").unwrap(), CqlValue::SmallInt(-32768));
+        assert!(parse("32768", "smallint").is_err());
+        assert!(parse("2147483648", "int").is_err());
+
+        assert_eq!(to_cql_value(&json!(-128), &ColumnType::TinyInt).unwrap(), CqlValue::TinyInt(-128));
+        assert!(to_cql_value(&json!(-129), &ColumnType::TinyInt).is_err());
+        assert!(to_cql_value(&json!(40000), &ColumnType::SmallInt).is_err());
+        assert!(to_cql_value(&json!(1.5), &ColumnType::Int).is_err());
+        assert!(to_cql_value(&json!(u64::MAX), &ColumnType::BigInt).is_err());
+    }
+
+    #[test]
+    fn accepts_only_version_1_uuids_as_timeuuids() {
+        let time_uuid = "e7d2b8a0-a86f-11ee-8c90-0242ac120002";
+        let random_uuid = "3f0c4d5e-8b6a-4c2d-9e1f-0a1b2c3d4e5f";
+
+        assert_eq!(parse(time_uuid, "timeuuid").unwrap(), CqlValue::Timeuuid(time_uuid.parse().unwrap()));
+        let error = parse(random_uuid, "timeuuid").unwrap_err();
+        assert!(error.to_string().ends_with("not a version 1 UUID"), "{error:?}");
+        assert_eq!(parse(random_uuid, "uuid").unwrap(), CqlValue::Uuid(random_uuid.parse().unwrap()));
+    }
+}
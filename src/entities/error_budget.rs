@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+/// How many rows may fail before a run is considered failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorBudget {
+    Rows(usize),
+    /// Percentage of the rows read, only known once the run is over
+    Percentage(f64),
+}
+
+impl ErrorBudget {
+    /// Whether `failed_rows` of `processed_rows` are more than the budget allows
+    pub fn is_exceeded(&self, failed_rows: usize, processed_rows: usize) -> bool {
+        match self {
+            ErrorBudget::Rows(max_rows) => failed_rows > *max_rows,
+            ErrorBudget::Percentage(percentage) => failed_rows as f64 > processed_rows as f64 * percentage / 100.0,
+        }
+    }
+
+    /// Whether the budget is already spent halfway through the run, when only an absolute count can tell
+    pub fn is_exceeded_early(&self, failed_rows: usize) -> bool {
+        matches!(self, ErrorBudget::Rows(max_rows) if failed_rows > *max_rows)
+    }
+}
+
+impl FromStr for ErrorBudget {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim().strip_suffix('%') {
+            Some(percentage) => {
+                let percentage = percentage.trim().parse::<f64>().map_err(|error| format!("invalid percentage {text:?}: {error}"))?;
+                if !(0.0..=100.0).contains(&percentage) {
+                    return Err(format!("percentage {text:?} is not between 0% and 100%"));
+                }
+                Ok(ErrorBudget::Percentage(percentage))
+            },
+            None => text.trim().parse::<usize>()
+                .map(ErrorBudget::Rows)
+                .map_err(|error| format!("expected a row count or a percentage, found {text:?}: {error}")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_row_counts_and_percentages() {
+        assert_eq!("10".parse::<ErrorBudget>(), Ok(ErrorBudget::Rows(10)));
+        assert_eq!(" 0 ".parse::<ErrorBudget>(), Ok(ErrorBudget::Rows(0)));
+        assert_eq!("1%".parse::<ErrorBudget>(), Ok(ErrorBudget::Percentage(1.0)));
+        assert_eq!("0.5 %".parse::<ErrorBudget>(), Ok(ErrorBudget::Percentage(0.5)));
+        assert_eq!("100%".parse::<ErrorBudget>(), Ok(ErrorBudget::Percentage(100.0)));
+
+        for text in ["-1", "1.5", "ten", "", "%", "101%", "-1%"] {
+            assert!(text.parse::<ErrorBudget>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn allows_up_to_a_row_count_at_any_time() {
+        let error_budget = ErrorBudget::Rows(10);
+
+        assert!(!error_budget.is_exceeded_early(10));
+        assert!(error_budget.is_exceeded_early(11));
+        assert!(!error_budget.is_exceeded(10, 10));
+        assert!(error_budget.is_exceeded(11, 1_000_000));
+    }
+
+    #[test]
+    fn allows_up_to_a_percentage_of_the_rows_read_at_the_end() {
+        let error_budget = ErrorBudget::Percentage(1.0);
+
+        assert!(!error_budget.is_exceeded_early(1_000_000));
+        assert!(!error_budget.is_exceeded(10, 1000));
+        assert!(error_budget.is_exceeded(11, 1000));
+        assert!(error_budget.is_exceeded(1, 0));
+        assert!(!ErrorBudget::Percentage(100.0).is_exceeded(1000, 1000));
+    }
+}
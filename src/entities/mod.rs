@@ -1,2 +1,23 @@
+mod batch_outcome;
+mod checkpoint;
+mod column_type;
+mod cql_literal;
 mod data_value;
-pub use data_value::DataValue;
+mod error_budget;
+mod rate_limit;
+mod rejected_row;
+mod row_failure;
+mod table_schema;
+mod transfer_error;
+mod transfer_summary;
+pub use batch_outcome::BatchOutcome;
+pub use checkpoint::{Checkpoint, FilePosition, SourceProgress};
+pub use column_type::ColumnType;
+pub use data_value::{DataValue, NamedValues};
+pub use error_budget::ErrorBudget;
+pub use rate_limit::RateLimit;
+pub use rejected_row::RejectedRow;
+pub use row_failure::{FailureStage, RowFailure};
+pub use table_schema::TableSchema;
+pub use transfer_error::TransferError;
+pub use transfer_summary::TransferSummary;
@@ -0,0 +1,88 @@
+/// Throughput a run may not exceed. `None` leaves it unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RateLimit {
+    pub rows_per_second: Option<u64>,
+    pub bytes_per_second: Option<u64>,
+}
+
+impl RateLimit {
+    /// Applies the `max-rows-per-second = N` and `max-bytes-per-second = N` lines of a control file to the
+    /// limit. A value of `0` or `none` removes that limit, and limits the file does not name are kept.
+    pub fn with_control_file(&self, text: &str) -> anyhow::Result<RateLimit> {
+        let mut rate_limit = *self;
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (name, value) = line.split_once('=').ok_or_else(|| anyhow::anyhow!("Expected `name = value`, found {line:?}"))?;
+
+            let value = match value.trim() {
+                "none" | "0" => None,
+                value => Some(value.parse::<u64>().map_err(|error| anyhow::anyhow!("Invalid value {value:?}: {error}"))?),
+            };
+
+            match name.trim() {
+                "max-rows-per-second" => rate_limit.rows_per_second = value,
+                "max-bytes-per-second" => rate_limit.bytes_per_second = value,
+                name => anyhow::bail!("Unknown setting {name:?}"),
+            }
+        }
+
+        Ok(rate_limit)
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.rows_per_second.is_none() && self.bytes_per_second.is_none()
+    }
+}
+
+impl std::fmt::Display for RateLimit {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |limit: Option<u64>| limit.map(|limit| limit.to_string()).unwrap_or_else(|| "unlimited".to_owned());
+        write!(formatter, "{} rows/s, {} bytes/s", describe(self.rows_per_second), describe(self.bytes_per_second))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE_LIMIT: RateLimit = RateLimit { rows_per_second: Some(1000), bytes_per_second: Some(65536) };
+
+    #[test]
+    fn applies_the_limits_of_the_file() {
+        let rate_limit = RATE_LIMIT.with_control_file("max-rows-per-second = 500\nmax-bytes-per-second=1024\n").unwrap();
+        assert_eq!(rate_limit, RateLimit { rows_per_second: Some(500), bytes_per_second: Some(1024) });
+    }
+
+    #[test]
+    fn removes_the_limits_set_to_none_or_0() {
+        let rate_limit = RATE_LIMIT.with_control_file("max-rows-per-second = none\nmax-bytes-per-second = 0").unwrap();
+        assert_eq!(rate_limit, RateLimit::default());
+    }
+
+    #[test]
+    fn keeps_the_limits_the_file_does_not_name() {
+        let rate_limit = RATE_LIMIT.with_control_file("max-rows-per-second = 10").unwrap();
+        assert_eq!(rate_limit, RateLimit { rows_per_second: Some(10), bytes_per_second: Some(65536) });
+
+        assert_eq!(RATE_LIMIT.with_control_file("").unwrap(), RATE_LIMIT);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let text = "# Slowed down during the day\n\n  # max-rows-per-second = 1\n   \nmax-rows-per-second = 200\n";
+        assert_eq!(RATE_LIMIT.with_control_file(text).unwrap().rows_per_second, Some(200));
+    }
+
+    #[test]
+    fn rejects_unknown_settings_and_invalid_lines() {
+        let error = RATE_LIMIT.with_control_file("max-rows-per-minute = 10").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown setting \"max-rows-per-minute\"");
+
+        let error = RATE_LIMIT.with_control_file("max-rows-per-second 10").unwrap_err();
+        assert_eq!(error.to_string(), "Expected `name = value`, found \"max-rows-per-second 10\"");
+
+        let error = RATE_LIMIT.with_control_file("max-rows-per-second = fast").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid value \"fast\""));
+    }
+}
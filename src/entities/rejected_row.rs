@@ -0,0 +1,28 @@
+use serde_json::{json, Value};
+
+use super::row_failure::FailureStage;
+
+/// A source record that could not be written, with what is needed to find, fix and load it again
+#[derive(Debug)]
+pub struct RejectedRow {
+    /// The row, or the source text of a record that could not be read
+    pub payload: Value,
+    pub source_path: String,
+    pub line_number: u64,
+    pub stage: FailureStage,
+    pub error: anyhow::Error,
+}
+
+impl RejectedRow {
+    /// The JSON line written to the rejects output. Loading it with the `/payload` JSON pointer
+    /// reads the rows again.
+    pub fn to_json_line(&self) -> String {
+        json!({
+            "payload": self.payload,
+            "source": self.source_path,
+            "line": self.line_number,
+            "stage": self.stage.to_string(),
+            "error": format!("{:#}", self.error),
+        }).to_string()
+    }
+}
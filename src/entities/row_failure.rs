@@ -0,0 +1,38 @@
+use std::fmt::Display;
+
+/// Step of the transference at which a row failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureStage {
+    /// Decoding the record from the source file
+    Read,
+    /// Converting the values to the column types
+    Convert,
+    /// Executing the insert statement
+    Write,
+}
+
+impl Display for FailureStage {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stage_name = match self {
+            FailureStage::Read => "read",
+            FailureStage::Convert => "convert",
+            FailureStage::Write => "write",
+        };
+
+        formatter.write_str(stage_name)
+    }
+}
+
+/// A row of a batch that could not be inserted
+#[derive(Debug)]
+pub struct RowFailure {
+    pub row_index: usize,
+    pub stage: FailureStage,
+    pub error: anyhow::Error,
+}
+
+impl RowFailure {
+    pub fn new(row_index: usize, stage: FailureStage, error: anyhow::Error) -> Self {
+        RowFailure { row_index, stage, error }
+    }
+}
@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+use super::ColumnType;
+
+/// Column types and partition key of the target table, loaded from `system_schema.columns`
+#[derive(Debug, Clone)]
+pub struct TableSchema {
+    columns: HashMap<String, ColumnType>,
+    partition_key: Vec<String>,
+}
+
+impl TableSchema {
+    pub fn new(columns: HashMap<String, ColumnType>, partition_key: Vec<String>) -> TableSchema {
+        TableSchema { columns, partition_key }
+    }
+
+    /// Partition key columns, in their order in the key
+    pub fn partition_key(&self) -> &[String] {
+        &self.partition_key
+    }
+
+    pub fn column_names(&self) -> impl Iterator<Item = &String> {
+        self.columns.keys()
+    }
+
+    pub fn column_type(&self, column_name: &str) -> Option<&ColumnType> {
+        self.columns.get(column_name)
+    }
+}
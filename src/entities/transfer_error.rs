@@ -0,0 +1,51 @@
+use std::fmt::Display;
+
+/// Failures that end a run, each with its own process exit code. Other errors, like an unreachable
+/// database, exit with code 1.
+#[derive(Debug)]
+pub enum TransferError {
+    /// The source files cannot be listed, opened or decoded
+    Source(anyhow::Error),
+    /// The target table does not exist, or does not match the source columns
+    Schema(anyhow::Error),
+    /// More rows failed than the error budget allows
+    WriteErrorsExceeded { failed_rows: usize, processed_rows: usize },
+}
+
+impl TransferError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            TransferError::Source(_) => 2,
+            TransferError::Schema(_) => 3,
+            TransferError::WriteErrorsExceeded { .. } => 4,
+        }
+    }
+
+    /// The exit code of the first transfer error in the chain of `error`
+    pub fn exit_code_of(error: &anyhow::Error) -> u8 {
+        error.chain()
+            .find_map(|cause| cause.downcast_ref::<TransferError>())
+            .map(TransferError::exit_code)
+            .unwrap_or(1)
+    }
+}
+
+impl Display for TransferError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::Source(_) => formatter.write_str("Source error"),
+            TransferError::Schema(_) => formatter.write_str("Schema error"),
+            TransferError::WriteErrorsExceeded { failed_rows, processed_rows } =>
+                write!(formatter, "Too many failed rows: {failed_rows} of {processed_rows}"),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::Source(error) | TransferError::Schema(error) => Some(error.as_ref()),
+            TransferError::WriteErrorsExceeded { .. } => None,
+        }
+    }
+}
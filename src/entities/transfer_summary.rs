@@ -0,0 +1,65 @@
+use std::time::Instant;
+
+use atomic_counter::{AtomicCounter, RelaxedCounter};
+
+use super::{rejected_row::RejectedRow, row_failure::FailureStage};
+
+/// Running totals of a transference, logged when it ends
+pub struct TransferSummary {
+    started_at: Instant,
+    loaded_files: RelaxedCounter,
+    read_rows: RelaxedCounter,
+    written_rows: RelaxedCounter,
+    invalid_rows: RelaxedCounter,
+    failed_writes: RelaxedCounter,
+    retries: RelaxedCounter,
+}
+
+impl TransferSummary {
+    pub fn new() -> Self {
+        TransferSummary {
+            started_at: Instant::now(),
+            loaded_files: RelaxedCounter::default(),
+            read_rows: RelaxedCounter::default(),
+            written_rows: RelaxedCounter::default(),
+            invalid_rows: RelaxedCounter::default(),
+            failed_writes: RelaxedCounter::default(),
+            retries: RelaxedCounter::default(),
+        }
+    }
+
+    pub fn add_loaded_file(&self) {
+        self.loaded_files.inc();
+    }
+
+    /// Counts the rows of an inserted batch, given the rows that failed and the writes retried
+    pub fn add_batch(&self, batch_size: usize, rejected_rows: &[RejectedRow], retries: usize) {
+        let failed_writes = rejected_rows.iter().filter(|rejected_row| rejected_row.stage == FailureStage::Write).count();
+
+        self.read_rows.add(batch_size);
+        self.written_rows.add(batch_size - rejected_rows.len());
+        self.invalid_rows.add(rejected_rows.len() - failed_writes);
+        self.failed_writes.add(failed_writes);
+        self.retries.add(retries);
+    }
+
+    pub fn read_rows(&self) -> usize {
+        self.read_rows.get()
+    }
+
+    pub fn failed_rows(&self) -> usize {
+        self.invalid_rows.get() + self.failed_writes.get()
+    }
+
+    pub fn log(&self) {
+        log::info!("Wrote {written} of {read} rows from {files} files in {elapsed:.1?}; {failed} rows failed ({invalid} invalid, {failed_writes} write errors); {retries} writes retried",
+            written=self.written_rows.get(), read=self.read_rows.get(), files=self.loaded_files.get(), elapsed=self.started_at.elapsed(),
+            failed=self.failed_rows(), invalid=self.invalid_rows.get(), failed_writes=self.failed_writes.get(), retries=self.retries.get());
+    }
+}
+
+impl Default for TransferSummary {
+    fn default() -> Self {
+        TransferSummary::new()
+    }
+}
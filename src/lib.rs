@@ -0,0 +1,4 @@
+pub mod persistence;
+pub mod processors;
+pub mod rate_limiter;
+pub mod entities;
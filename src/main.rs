@@ -1,28 +1,151 @@
+use std::{process::ExitCode, sync::Arc, time::Duration};
+
 use clap::Parser;
-use persistence::files_system::Dataset;
-use processors::run_transference;
-use crate::{command_line::CommandLine, persistence::DatabaseClient};
+use scylladb_uploader::entities::{RateLimit, TransferError};
+use scylladb_uploader::persistence::{DatabaseClient, RetryOptions, SessionOptions, TlsOptions, WriteOptions};
+use scylladb_uploader::persistence::files_system::{CheckpointStore, CsvDialect, Dataset, RejectWriter, S3Options, SourceOptions, is_s3_path,
+                                                  make_s3_client};
+use scylladb_uploader::processors::{TransferOptions, run_transferences};
+use crate::command_line::CommandLine;
 
-mod persistence;
 mod command_line;
-mod processors;
-mod entities;
 
 
-#[tokio::main(flavor="current_thread")]
-async fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     env_logger::init();
     let arguments = CommandLine::parse();
-    
-    let database_client =
-        DatabaseClient::new(&arguments.database_nodes, &arguments.database_username, &arguments.database_password, &arguments.database_keyspace_name, &arguments.database_table).await?;
 
-    let dataset = Dataset::load(&arguments.source_path, &arguments.source_file_type, arguments.s3_access_key, arguments.s3_secret_access_key,
-                                        arguments.s3_region, arguments.s3_endpoint).await?;
+    let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = arguments.worker_threads {
+        runtime_builder.worker_threads(worker_threads.get());
+    }
+
+    let runtime = match runtime_builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            eprintln!("Error: cannot start the runtime: {error}");
+            return ExitCode::FAILURE;
+        },
+    };
+
+    match runtime.block_on(run(arguments)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            ExitCode::from(TransferError::exit_code_of(&error))
+        },
+    }
+}
+
+async fn run(arguments: CommandLine) -> anyhow::Result<()> {
+    let csv_dialect = CsvDialect {
+        delimiter: arguments.csv_delimiter,
+        quote: arguments.csv_quote,
+        escape: arguments.csv_escape,
+        comment: arguments.csv_comment,
+        column_names: arguments.csv_columns,
+        null: arguments.csv_null,
+    };
+
+    let s3_options = S3Options {
+        access_key: arguments.s3_access_key,
+        secret_key: arguments.s3_secret_access_key,
+        region: arguments.s3_region,
+        endpoint: arguments.s3_endpoint,
+        profile: arguments.s3_profile,
+        role_arn: arguments.s3_role_arn,
+        force_path_style: arguments.s3_force_path_style,
+    };
+
+    // One client serves every S3 access of the run, and none is built when all the paths are local
+    let uses_s3 = [Some(&arguments.source_path), arguments.reject_path.as_ref(), arguments.checkpoint_path.as_ref()].into_iter()
+        .flatten()
+        .any(|path| is_s3_path(path));
+    let s3_client = match uses_s3 {
+        true => Some(make_s3_client(&s3_options).await.map_err(TransferError::Source)?),
+        false => None,
+    };
+
+    let source_paths = Dataset::list_sources(&arguments.source_path, s3_client.as_ref(), &arguments.source_include, &arguments.source_exclude).await
+        .map_err(TransferError::Source)?;
+
+    if arguments.tls_insecure_skip_verify {
+        log::warn!("Node certificates are not verified (--tls-insecure-skip-verify): the connections are open to interception");
+    } else if arguments.tls && arguments.tls_verify_hostname.is_none() {
+        log::warn!("Node certificates are not matched to a host name: any certificate of the trusted authorities is accepted for any node");
+    }
+
+    let session_options = SessionOptions {
+        consistency: arguments.consistency,
+        serial_consistency: arguments.serial_consistency,
+        request_timeout: Duration::from_secs(arguments.request_timeout),
+        connection_timeout: Duration::from_secs(arguments.connection_timeout),
+        tls_options: arguments.tls.then_some(TlsOptions {
+            ca_file: arguments.tls_ca_file,
+            cert_file: arguments.tls_cert_file,
+            key_file: arguments.tls_key_file,
+            verify: !arguments.tls_insecure_skip_verify,
+            verify_hostname: arguments.tls_verify_hostname,
+        }),
+        local_datacenter: arguments.local_datacenter,
+        preferred_rack: arguments.preferred_rack,
+        token_aware: arguments.token_aware,
+        shard_aware: arguments.shard_aware,
+        connections_per_shard: arguments.connections_per_shard,
+        compression: arguments.compression,
+    };
+
+    let write_options = WriteOptions {
+        column_binding: arguments.column_binding,
+        write_mode: arguments.write_mode,
+        max_batch_rows: arguments.max_batch_rows,
+        max_batch_bytes: arguments.max_batch_bytes,
+        max_requests_in_flight: arguments.max_requests_in_flight,
+        target_p99_latency: arguments.adaptive_concurrency.then(|| Duration::from_millis(arguments.target_p99_latency)),
+        retry_options: RetryOptions {
+            max_attempts: arguments.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(arguments.retry_initial_backoff),
+            max_backoff: Duration::from_millis(arguments.retry_max_backoff),
+            retryable_errors: arguments.retry_on,
+        },
+    };
+
+    let database_client = Arc::new(
+        DatabaseClient::new(&arguments.database_nodes, &arguments.database_username, &arguments.database_password, &arguments.database_keyspace_name, &arguments.database_table,
+                            &session_options, write_options).await?);
+
+    let source_options = SourceOptions {
+        file_type: arguments.source_file_type,
+        compression: arguments.source_compression,
+        csv_dialect,
+        json_pointer: arguments.json_pointer.unwrap_or_default(),
+        s3_client,
+    };
+
+    let transfer_options = TransferOptions {
+        batch_size: arguments.batch_size,
+        concurrent_batches: arguments.concurrent_batches,
+        source_parallelism: arguments.source_parallelism,
+        error_budget: arguments.max_errors,
+        resume: arguments.resume,
+        checkpoint_interval: Duration::from_secs(arguments.checkpoint_interval),
+        rate_limit: RateLimit {
+            rows_per_second: arguments.max_rows_per_second.filter(|limit| *limit > 0),
+            bytes_per_second: arguments.max_bytes_per_second.filter(|limit| *limit > 0),
+        },
+        rate_control_file: arguments.rate_control_file,
+    };
+
+    let reject_writer = match &arguments.reject_path {
+        Some(reject_path) => Some(RejectWriter::create(reject_path, source_options.s3_client.as_ref(), arguments.resume).await?),
+        None => None,
+    };
+
+    let checkpoint_store = arguments.checkpoint_path.as_ref()
+        .map(|checkpoint_path| CheckpointStore::new(checkpoint_path, source_options.s3_client.as_ref()));
 
-    run_transference(&database_client, &dataset, arguments.batch_size, arguments.concurrent_batches).await?;
-    
-    database_client.wait().await;
+    run_transferences(database_client, source_paths, &source_options, &transfer_options, reject_writer.as_ref(),
+                      checkpoint_store.as_ref()).await?;
 
     Ok(())
 }
@@ -0,0 +1,87 @@
+use scylla::frame::{response::result::CqlValue, value::{MaybeUnset, Value}};
+
+use crate::entities::NamedValues;
+
+/// The values of a row in the order of its sorted column names, the order of the placeholders of its
+/// insert statement. CQL batches only take values by position.
+pub struct BoundRow {
+    pub row_index: usize,
+    pub column_names: Vec<String>,
+    pub values: Vec<MaybeUnset<CqlValue>>,
+}
+
+impl BoundRow {
+    pub fn new(row_index: usize, named_values: NamedValues) -> Self {
+        let mut named_values = named_values.into_iter().collect::<Vec<_>>();
+        named_values.sort_by(|(first_name, _), (second_name, _)| first_name.cmp(second_name));
+
+        let (column_names, values) = named_values.into_iter().unzip();
+        BoundRow { row_index, column_names, values }
+    }
+
+    /// The serialized values of the partition key columns, equal for the rows of the same partition
+    pub fn partition_key(&self, partition_key: &[String]) -> anyhow::Result<Vec<u8>> {
+        let mut serialized_key = Vec::new();
+
+        for column_name in partition_key {
+            match self.column_names.binary_search(column_name) {
+                Ok(position) => self.values[position].serialize(&mut serialized_key)?,
+                Err(_) => MaybeUnset::<CqlValue>::Unset.serialize(&mut serialized_key)?,
+            }
+        }
+
+        Ok(serialized_key)
+    }
+
+    /// Bytes the values of the row take in a request
+    pub fn serialized_size(&self) -> anyhow::Result<usize> {
+        let mut serialized_values = Vec::new();
+        for value in &self.values {
+            value.serialize(&mut serialized_values)?;
+        }
+
+        Ok(serialized_values.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound_row(values: &[(&str, CqlValue)]) -> BoundRow {
+        let named_values = values.iter().map(|(column_name, value)| (column_name.to_string(), MaybeUnset::Set(value.clone()))).collect();
+        BoundRow::new(0, named_values)
+    }
+
+    fn serialized(values: &[MaybeUnset<CqlValue>]) -> Vec<u8> {
+        let mut serialized_values = Vec::new();
+        for value in values {
+            value.serialize(&mut serialized_values).unwrap();
+        }
+        serialized_values
+    }
+
+    #[test]
+    fn serializes_a_composite_key_in_key_order() {
+        let row = bound_row(&[("user_id", CqlValue::Int(7)), ("bucket", CqlValue::Text("a".to_owned())), ("name", CqlValue::Text("Ana".to_owned()))]);
+        assert_eq!(row.column_names, ["bucket", "name", "user_id"]);
+
+        let partition_key = ["user_id".to_owned(), "bucket".to_owned()];
+        let expected_key = serialized(&[MaybeUnset::Set(CqlValue::Int(7)), MaybeUnset::Set(CqlValue::Text("a".to_owned()))]);
+        assert_eq!(row.partition_key(&partition_key).unwrap(), expected_key);
+    }
+
+    #[test]
+    fn groups_rows_missing_a_key_column_as_unset() {
+        let partition_key = ["user_id".to_owned(), "bucket".to_owned()];
+        let first_row = bound_row(&[("user_id", CqlValue::Int(7)), ("name", CqlValue::Text("Ana".to_owned()))]);
+        let second_row = bound_row(&[("user_id", CqlValue::Int(7)), ("name", CqlValue::Text("Bruno".to_owned()))]);
+        let other_row = bound_row(&[("user_id", CqlValue::Int(7)), ("bucket", CqlValue::Text("".to_owned()))]);
+
+        let expected_key = serialized(&[MaybeUnset::Set(CqlValue::Int(7)), MaybeUnset::Unset]);
+        assert_eq!(first_row.partition_key(&partition_key).unwrap(), expected_key);
+        assert_eq!(second_row.partition_key(&partition_key).unwrap(), expected_key);
+        assert_ne!(other_row.partition_key(&partition_key).unwrap(), expected_key);
+    }
+}
@@ -0,0 +1,10 @@
+/// Which columns the insert of a row binds
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnBinding {
+    /// The fields of each row, with a statement prepared for every distinct set of fields
+    Row,
+    /// Every column of the table, leaving the ones absent from the row unset
+    Table,
+    /// Every column of the table, rejecting the rows that miss any of them
+    Strict,
+}
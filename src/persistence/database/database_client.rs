@@ -1,115 +1,415 @@
-use std::{cell::RefCell, collections::HashMap, sync::Arc};
+use std::{collections::HashMap, future::Future, sync::{Arc, Mutex}, time::Instant};
 use atomic_counter::{AtomicCounter, RelaxedCounter};
-use scylla::{Session, SessionBuilder, prepared_statement::PreparedStatement};
-use wg::AsyncWaitGroup;
-use crate::entities::DataValue;
+use futures::future;
+use scylla::{ExecutionProfile, Session, SessionBuilder, batch::{Batch, BatchType}, frame::value::MaybeUnset, load_balancing::DefaultPolicy,
+             prepared_statement::PreparedStatement, retry_policy::FallthroughRetryPolicy, transport::{errors::QueryError, session::PoolSize}};
+use tokio::sync::OnceCell;
+use crate::entities::{BatchOutcome, DataValue, FailureStage, NamedValues, RowFailure, TableSchema, TransferError};
 
+use super::{bound_row::BoundRow, column_binding::ColumnBinding, request_limiter::RequestLimiter, session_options::SessionOptions,
+            table_schema_loader::load_table_schema, tls_options::TlsOptions, write_options::{DEFAULT_REQUESTS_PER_CONNECTION, WriteMode, WriteOptions}};
 
+
+/// Writes rows to a table. It is shared by the tasks inserting batches, so the statements it prepares for
+/// each set of columns are cached behind a lock, and each one is prepared only once.
 pub struct DatabaseClient {
     session: Arc<Session>,
     keyspace_name: String,
     table_name: String,
+    table_schema: Arc<TableSchema>,
+    write_options: WriteOptions,
 
     total_batches: Arc<RelaxedCounter>,
-    prepared_statement: RefCell<Option<PreparedStatement>>,
-    field_names: RefCell<Vec<String>>,
-    wait_group: RefCell<AsyncWaitGroup>,
+    request_limiter: RequestLimiter,
+    /// Insert statements by their sorted column names
+    prepared_statements: Mutex<HashMap<Vec<String>, Arc<OnceCell<PreparedStatement>>>>,
 }
 
 impl DatabaseClient {
     
-    pub async fn new(nodes_string: &str, username: &str, password: &str, keyspace_name: &str,  table_name: &str) -> anyhow::Result<DatabaseClient> {
-        let nodes = nodes_string.split(",").map(|u| u.to_owned() ).collect();
-        let session = make_session(username, password, nodes).await?;
+    pub async fn new(nodes_string: &str, username: &str, password: &str, keyspace_name: &str,  table_name: &str,
+                     session_options: &SessionOptions, write_options: WriteOptions) -> anyhow::Result<DatabaseClient> {
+        let nodes = nodes_string.split(',').map(|u| u.to_owned() ).collect();
+        let session = make_session(username, password, nodes, session_options).await?;
+        let table_schema = load_table_schema(&session, keyspace_name, table_name).await.map_err(TransferError::Schema)?;
+
+        let connections = count_connections(&session, session_options);
+        let max_requests = write_options.max_requests_in_flight.unwrap_or(connections * DEFAULT_REQUESTS_PER_CONNECTION).max(1);
+        log::info!("Sending up to {max_requests} requests at a time over {connections} connections");
+
+        // An adaptive limit starts at one request per connection and grows by as much
+        let request_limiter = match write_options.target_p99_latency {
+            Some(target_p99_latency) => RequestLimiter::adaptive(target_p99_latency, connections, max_requests, connections),
+            None => RequestLimiter::fixed(max_requests),
+        };
 
         let database_client =
             DatabaseClient {
                 session,
                 keyspace_name: keyspace_name.to_owned(),
                 table_name: table_name.to_owned(),
+                table_schema: Arc::new(table_schema),
+                write_options,
                 total_batches: Arc::new(RelaxedCounter::new(0)),
-                prepared_statement: RefCell::new(None),
-                field_names: RefCell::new(Vec::new()),
-                wait_group: RefCell::new(AsyncWaitGroup::new()),
+                request_limiter,
+                prepared_statements: Mutex::new(HashMap::new()),
             };
 
         Ok(database_client)
     }
 
-    pub async fn insert_batch(&self, batch: Vec<serde_json::Value>) -> anyhow::Result<()> {
-        if self.prepared_statement.borrow().is_none() {
-            self.update_prepared_statement_and_field_names(&batch).await?;
+    /// Inserts the rows of the batch, each with the statement of its columns, either one by one or in UNLOGGED
+    /// batches by partition. The requests are issued concurrently, as request permits allow, and routed by the
+    /// driver to the replicas and shards owning their partitions. Returns once all of them are written, with
+    /// the rows that failed and the number of retries.
+    pub async fn insert_batch(&self, batch: &[serde_json::Value]) -> anyhow::Result<BatchOutcome> {
+        let mut outcome = BatchOutcome::default();
+        let mut bound_rows = Vec::with_capacity(batch.len());
+
+        for (row_index, row) in batch.iter().enumerate() {
+            match self.bind_values(row) {
+                Ok(values) => bound_rows.push(BoundRow::new(row_index, values)),
+                Err(error) => outcome.failures.push(RowFailure::new(row_index, FailureStage::Convert, error)),
+            }
+        }
+
+        // A statement that cannot be prepared, even after retries, fails the rows of its columns like a write
+        let mut prepare_results = HashMap::new();
+        for bound_row in &bound_rows {
+            if !prepare_results.contains_key(&bound_row.column_names) {
+                let (prepare_result, retries) = self.prepared_statement(&bound_row.column_names).await;
+                outcome.retries += retries;
+                prepare_results.insert(bound_row.column_names.clone(), prepare_result.map_err(|error| format!("{error:#}")));
+            }
         }
-        
-        let preapared_statement = self.prepared_statement.borrow().clone().unwrap();
-        let session = self.session.clone();
 
-        let upload_batch_task = upload_batch(session, batch, preapared_statement, self.wait_group.borrow().clone(), self.total_batches.clone());
+        let (bound_rows, unprepared_rows): (Vec<_>, Vec<_>) = bound_rows.into_iter()
+            .partition(|bound_row| prepare_results[&bound_row.column_names].is_ok());
+
+        for bound_row in unprepared_rows {
+            if let Err(error) = &prepare_results[&bound_row.column_names] {
+                let error = anyhow::anyhow!("Cannot prepare the insert of its columns: {error}");
+                outcome.failures.push(RowFailure::new(bound_row.row_index, FailureStage::Write, error));
+            }
+        }
+
+        let prepared_statements = prepare_results.into_iter()
+            .filter_map(|(column_names, prepare_result)| Some((column_names, prepare_result.ok()?)))
+            .collect::<HashMap<_, _>>();
+
+        match self.write_options.write_mode {
+            WriteMode::Rows => self.execute_rows(&bound_rows, &prepared_statements, &mut outcome).await?,
+            WriteMode::UnloggedBatches => self.execute_unlogged_batches(bound_rows, &prepared_statements, &mut outcome).await?,
+        }
+
+        outcome.failures.sort_by_key(|failure| failure.row_index);
+        self.total_batches.inc();
+
+        log::info!("Batch #{batch_id} uploaded", batch_id=self.total_batches.get());
+
+        Ok(outcome)
+    }
+
+    async fn execute_rows(&self, bound_rows: &[BoundRow], prepared_statements: &HashMap<Vec<String>, PreparedStatement>,
+                          outcome: &mut BatchOutcome) -> anyhow::Result<()> {
+        let row_outcomes = future::try_join_all(bound_rows.iter().map(|bound_row| async move {
+            let prepared_statement = &prepared_statements[&bound_row.column_names];
+
+            let (result, retries) = self.with_retries(|| self.session.execute(prepared_statement, &bound_row.values)).await?;
+            let failure = result.err()
+                .map(|error| RowFailure::new(bound_row.row_index, FailureStage::Write, error.into()));
+            anyhow::Ok((failure, retries))
+        })).await?;
+
+        for (failure, retries) in row_outcomes {
+            outcome.failures.extend(failure);
+            outcome.retries += retries;
+        }
 
-        self.wait_group.borrow().add(1);
-        tokio::spawn(upload_batch_task);
-        
         Ok(())
     }
 
-    async fn update_prepared_statement_and_field_names(&self, batch: &Vec<serde_json::Value>) -> anyhow::Result<()> {
-        if let Some(first_batch_element) = batch.first() {
-            if let Some(sample_line) = first_batch_element.as_object() {
-                let field_names = sample_line.keys().map(|field_name| field_name.to_owned()).collect::<Vec<_>>();
-                let prepared_statement =
-                    make_prepared_statement(&self.session, &self.keyspace_name, &self.table_name, &field_names[..])
-                    .await?;
-                
-                self.prepared_statement.replace_with(|_| Some(prepared_statement));
-                self.field_names.replace_with(|_| field_names);
-            }    
+    /// Groups the rows by partition key and sends each group in UNLOGGED batches of at most `max_batch_rows`
+    /// rows and `max_batch_bytes` bytes of values. When a batch fails, all of its rows fail.
+    async fn execute_unlogged_batches(&self, bound_rows: Vec<BoundRow>, prepared_statements: &HashMap<Vec<String>, PreparedStatement>,
+                                      outcome: &mut BatchOutcome) -> anyhow::Result<()> {
+        let mut partitions: Vec<Vec<(BoundRow, usize)>> = Vec::new();
+        let mut partition_positions = HashMap::new();
+
+        for bound_row in bound_rows {
+            let sized_key = bound_row.partition_key(self.table_schema.partition_key())
+                .and_then(|partition_key| Ok((partition_key, bound_row.serialized_size()?)));
+
+            match sized_key {
+                Ok((partition_key, row_size)) => {
+                    let position = *partition_positions.entry(partition_key).or_insert_with(|| {
+                        partitions.push(Vec::new());
+                        partitions.len() - 1
+                    });
+                    partitions[position].push((bound_row, row_size));
+                },
+                Err(error) => outcome.failures.push(RowFailure::new(bound_row.row_index, FailureStage::Convert, error)),
+            }
+        }
+
+        let cql_batches = partitions.into_iter()
+            .flat_map(|partition| split_batch(partition, self.write_options.max_batch_rows, self.write_options.max_batch_bytes));
+
+        let batch_outcomes = future::try_join_all(cql_batches.map(|cql_batch_rows| async move {
+            let mut cql_batch = Batch::new(BatchType::Unlogged);
+            cql_batch.set_is_idempotent(true);
+            for bound_row in &cql_batch_rows {
+                cql_batch.append_statement(prepared_statements[&bound_row.column_names].clone());
+            }
+
+            let values = cql_batch_rows.iter().map(|bound_row| bound_row.values.as_slice()).collect::<Vec<_>>();
+
+            let (result, retries) = self.with_retries(|| self.session.batch(&cql_batch, &values)).await?;
+            let Err(error) = result else {
+                return anyhow::Ok((Vec::new(), retries));
+            };
+
+            let rows_count = cql_batch_rows.len();
+            let failures = cql_batch_rows.iter().map(|bound_row| {
+                let error = anyhow::anyhow!("UNLOGGED batch of {rows_count} rows failed: {error}");
+                RowFailure::new(bound_row.row_index, FailureStage::Write, error)
+            }).collect::<Vec<_>>();
+            anyhow::Ok((failures, retries))
+        })).await?;
+
+        for (failures, retries) in batch_outcomes {
+            outcome.failures.extend(failures);
+            outcome.retries += retries;
         }
 
         Ok(())
     }
 
-    pub async fn wait(&self) -> () {
-        self.wait_group.borrow().wait().await;
+    /// Sends a request until it succeeds, fails with an error that is not retryable, or runs out of attempts,
+    /// backing off between attempts. Each attempt holds a request permit, released while backing off, and
+    /// reports its latency and errors to the request limiter.
+    /// Returns the last result, with the number of retries.
+    async fn with_retries<T, R>(&self, request: impl Fn() -> R) -> anyhow::Result<(Result<T, QueryError>, usize)>
+        where R: Future<Output = Result<T, QueryError>> {
+
+        let retry_options = &self.write_options.retry_options;
+        let mut attempt = 1;
+
+        loop {
+            let permit = self.request_limiter.acquire().await?;
+            let started = Instant::now();
+            let result = request().await;
+            self.request_limiter.release(permit, started.elapsed(), &result);
+
+            match result {
+                Err(error) if retry_options.should_retry(&error, attempt) => {
+                    let backoff = retry_options.backoff(attempt);
+                    log::debug!("Retrying a write in {backoff:?} after attempt {attempt} failed: {error}");
+
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                },
+                result => return Ok((result, attempt as usize - 1)),
+            }
+        }
+    }
+
+    /// Converts a row into the values of the columns it binds. Binding the table columns leaves the
+    /// ones missing from the row unset, or rejects the row when strict.
+    fn bind_values(&self, row: &serde_json::Value) -> anyhow::Result<NamedValues> {
+        let mut values = DataValue::new(row.clone()).into_named_values(&self.table_schema)?;
+
+        let column_binding = self.write_options.column_binding;
+        if column_binding != ColumnBinding::Row {
+            let mut missing_columns = self.table_schema.column_names()
+                .filter(|column_name| !values.contains_key(*column_name))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if column_binding == ColumnBinding::Strict && !missing_columns.is_empty() {
+                missing_columns.sort();
+                anyhow::bail!("Missing columns {}", missing_columns.join(", "))
+            }
+
+            values.extend(missing_columns.into_iter().map(|column_name| (column_name, MaybeUnset::Unset)));
+        }
+
+        Ok(values)
+    }
+
+    /// The insert statement of the columns, prepared once and retried as writes are. Returns the number of
+    /// retries along with it.
+    async fn prepared_statement(&self, column_names: &[String]) -> (anyhow::Result<PreparedStatement>, usize) {
+        let statement_cell = self.prepared_statements.lock().unwrap()
+            .entry(column_names.to_vec())
+            .or_default()
+            .clone();
+
+        let mut retries = 0;
+        let prepared_statement = statement_cell.get_or_try_init(|| async {
+            log::info!("Preparing the insert of columns {columns}", columns=column_names.join(", "));
+            let query = insert_query(&self.keyspace_name, &self.table_name, column_names);
+
+            let (result, prepare_retries) = self.with_retries(|| self.session.prepare(query.as_str())).await?;
+            retries = prepare_retries;
+
+            let mut prepared_statement = result?;
+            // Inserting a row again writes the same cells, so a failed insert can be retried
+            prepared_statement.set_is_idempotent(true);
+            anyhow::Ok(prepared_statement)
+        }).await;
+
+        (prepared_statement.cloned(), retries)
     }
 }
 
 
-async fn make_session(username: &str, password: &str, nodes: Vec<String>) -> anyhow::Result<Arc<Session>> {
+/// Opens the session, over TLS when its options are set. Its default execution profile carries the load
+/// balancing, consistency and request timeout, so the prepared statements and batches use them.
+async fn make_session(username: &str, password: &str, nodes: Vec<String>, session_options: &SessionOptions) -> anyhow::Result<Arc<Session>> {
+    let mut load_balancing_policy_builder = DefaultPolicy::builder()
+        .token_aware(session_options.token_aware);
+
+    // Without failover, the requests stay in the local datacenter
+    if let Some(local_datacenter) = &session_options.local_datacenter {
+        load_balancing_policy_builder = load_balancing_policy_builder.prefer_datacenter(local_datacenter.clone());
+    }
+
+    if let Some(preferred_rack) = &session_options.preferred_rack {
+        load_balancing_policy_builder = load_balancing_policy_builder.prefer_rack(preferred_rack.clone());
+    }
+
+    let load_balancing_policy = load_balancing_policy_builder.build();
+
+    let mut execution_profile_builder = ExecutionProfile::builder()
+        .load_balancing_policy(load_balancing_policy)
+        .retry_policy(Box::new(FallthroughRetryPolicy::new()))
+        .consistency(session_options.consistency.into())
+        .request_timeout(Some(session_options.request_timeout));
+
+    if let Some(serial_consistency) = session_options.serial_consistency {
+        execution_profile_builder = execution_profile_builder.serial_consistency(Some(serial_consistency.into()));
+    }
+
+    let execution_profile = execution_profile_builder.build();
+
+    let ssl_context = session_options.tls_options.as_ref().map(TlsOptions::ssl_context).transpose()?;
+
+    let pool_size = match session_options.shard_aware {
+        true => PoolSize::PerShard(session_options.connections_per_shard),
+        false => PoolSize::PerHost(session_options.connections_per_shard),
+    };
+
     let session =
         SessionBuilder::new()
             .known_nodes(nodes)
             .user(username, password)
+            .connection_timeout(session_options.connection_timeout)
+            .ssl_context(ssl_context)
+            .pool_size(pool_size)
+            .disallow_shard_aware_port(!session_options.shard_aware)
+            .compression(session_options.compression.map(Into::into))
+            .default_execution_profile_handle(execution_profile.into_handle())
             .build()
             .await?;
 
     Ok(Arc::new(session))
 }
 
+/// Connections of the session pools to the nodes requests are sent to, those of the local datacenter when
+/// set. They are opened to each shard of the nodes when shard aware, or to the nodes whose shards are not known.
+fn count_connections(session: &Session, session_options: &SessionOptions) -> usize {
+    let local_datacenter = session_options.local_datacenter.as_ref();
 
-async fn make_prepared_statement(session: &Session, keyspace_name: &str, table_name: &str, field_names: &[String]) -> anyhow::Result<PreparedStatement> {
-    let placeholders = field_names.iter().map(|field_name| format!(":{field_name}")).collect::<Vec<_>>().join(", ");
-    let field_names_string = field_names.join(", ");
+    session.get_cluster_data().get_nodes_info().iter()
+        .filter(|node| local_datacenter.is_none() || node.datacenter.as_ref() == local_datacenter)
+        .map(|node| match (session_options.shard_aware, node.sharder()) {
+            (true, Some(sharder)) => usize::from(sharder.nr_shards.get()),
+            _ => 1,
+        })
+        .sum::<usize>()
+        .max(1) * session_options.connections_per_shard.get()
+}
 
-    let query = format!("INSERT INTO {keyspace_name}.{table_name} ({field_names_string}) VALUES ({placeholders})");
-    let prepared =
-        session
-            .prepare(query)
-            .await?;
 
-    Ok(prepared)
+/// The insert of the columns, with the names quoted as `system_schema` has them, so mixed case and reserved
+/// words work, and values bound by position, in the order of the columns
+fn insert_query(keyspace_name: &str, table_name: &str, field_names: &[String]) -> String {
+    let field_names_string = field_names.iter().map(|field_name| quote_identifier(field_name)).collect::<Vec<_>>().join(", ");
+    let placeholders = vec!["?"; field_names.len()].join(", ");
+
+    format!("INSERT INTO {keyspace}.{table} ({field_names_string}) VALUES ({placeholders})",
+        keyspace=quote_identifier(keyspace_name), table=quote_identifier(table_name))
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
 }
 
-async fn upload_batch(session: Arc<Session>, batch: Vec<serde_json::Value>, preapared_statement: PreparedStatement, wait_group: AsyncWaitGroup, total_batches: Arc<RelaxedCounter>) -> Result<(), anyhow::Error> {
 
-    for serde_values in batch.iter() {
-        let values: HashMap<String, DataValue> = DataValue::new(serde_values.to_owned()).into();
-        session.execute(&preapared_statement, &values).await?;
+/// Splits the rows of a partition into batches of at most `max_rows` rows and `max_bytes` bytes. A row
+/// larger than `max_bytes` goes in a batch of its own, as does every row when `max_rows` is 0.
+fn split_batch(rows: Vec<(BoundRow, usize)>, max_rows: usize, max_bytes: usize) -> Vec<Vec<BoundRow>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+
+    for (row, row_size) in rows {
+        if !batch.is_empty() && (batch.len() >= max_rows || batch_bytes + row_size > max_bytes) {
+            batches.push(std::mem::take(&mut batch));
+            batch_bytes = 0;
+        }
+
+        batch.push(row);
+        batch_bytes += row_size;
     }
 
-    wait_group.done();
-    total_batches.inc();
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
 
-    log::info!("Batch #{batch_id} uploaded", batch_id=total_batches.get());
+    batches
+}
 
-    Ok(())
-}
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Splits rows of the given sizes, returning the indexes of the rows of each batch
+    fn split_sizes(row_sizes: &[usize], max_rows: usize, max_bytes: usize) -> Vec<Vec<usize>> {
+        let rows = row_sizes.iter().enumerate().map(|(row_index, row_size)| (BoundRow::new(row_index, HashMap::new()), *row_size)).collect();
+
+        split_batch(rows, max_rows, max_bytes).into_iter()
+            .map(|batch| batch.into_iter().map(|row| row.row_index).collect())
+            .collect()
+    }
+
+    #[test]
+    fn quotes_the_identifiers_of_inserts() {
+        let field_names = ["id".to_owned(), "Name".to_owned(), "select".to_owned(), "say \"hi\"".to_owned()];
+
+        assert_eq!(insert_query("Shop", "user events", &field_names),
+                   r#"INSERT INTO "Shop"."user events" ("id", "Name", "select", "say ""hi""") VALUES (?, ?, ?, ?)"#);
+    }
+
+    #[test]
+    fn splits_batches_by_rows_and_bytes() {
+        assert_eq!(split_sizes(&[10, 10, 10, 10, 10], 2, 100), [vec![0, 1], vec![2, 3], vec![4]]);
+        assert_eq!(split_sizes(&[10, 20, 30, 10], 10, 30), [vec![0, 1], vec![2], vec![3]]);
+        assert_eq!(split_sizes(&[], 10, 30), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn puts_a_row_larger_than_the_bytes_limit_in_a_batch_of_its_own() {
+        assert_eq!(split_sizes(&[10, 100, 10, 10], 10, 50), [vec![0], vec![1], vec![2, 3]]);
+        assert_eq!(split_sizes(&[100], 10, 50), [vec![0]]);
+    }
+
+    #[test]
+    fn puts_every_row_in_a_batch_of_its_own_with_a_rows_limit_of_0() {
+        assert_eq!(split_sizes(&[10, 10, 10], 0, 100), [vec![0], vec![1], vec![2]]);
+    }
+}
@@ -1,2 +1,15 @@
+mod bound_row;
+mod column_binding;
 mod database_client;
+mod request_limiter;
+mod retry_options;
+mod session_options;
+mod table_schema_loader;
+mod tls_options;
+mod write_options;
+pub use column_binding::ColumnBinding;
 pub use database_client::DatabaseClient;
+pub use retry_options::{RetryOptions, RetryableError};
+pub use session_options::{ConsistencyLevel, FrameCompression, SerialConsistencyLevel, SessionOptions};
+pub use tls_options::TlsOptions;
+pub use write_options::{WriteMode, WriteOptions};
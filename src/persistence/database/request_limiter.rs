@@ -0,0 +1,300 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use scylla::transport::errors::QueryError;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::retry_options::RetryableError;
+
+/// Time over which latencies are gathered before the limit is reconsidered
+const ADJUSTMENT_WINDOW: Duration = Duration::from_secs(1);
+/// Share of the limit kept when the p99 latency goes over the target
+const LATENCY_DECREASE: f64 = 0.9;
+/// Share of the limit kept after a timeout or an overloaded coordinator
+const ERROR_DECREASE: f64 = 0.5;
+
+/// Bounds the requests in flight over all the batches being inserted. The bound is fixed, or adapts AIMD
+/// style: it grows by `increase_step` after each window whose p99 latency stays under the target, shrinks
+/// a little when the p99 goes over it, and is halved right away on a timeout or an overloaded coordinator.
+pub struct RequestLimiter {
+    permits: Semaphore,
+    adaptive_limit: Option<Mutex<AdaptiveLimit>>,
+}
+
+struct AdaptiveLimit {
+    target_p99_latency: Duration,
+    min_limit: usize,
+    max_limit: usize,
+    increase_step: usize,
+
+    limit: usize,
+    /// Permits to drop as they are released, when the limit shrank below the requests in flight
+    permits_to_forget: usize,
+    window_start: Instant,
+    latencies: Vec<Duration>,
+    /// When the limit was last decreased for an error
+    last_overload: Option<Instant>,
+}
+
+/// The permit of a request in flight. Dropping it gives the permit back, or drops it when the limit shrank
+/// below the requests in flight, which holds for the requests cancelled before they are released too.
+pub struct RequestPermit<'a> {
+    request_limiter: &'a RequestLimiter,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+/// Outcome of a request, as far as the adaptive limit is concerned
+enum Signal {
+    Latency(Duration),
+    Overload,
+}
+
+impl RequestLimiter {
+    pub fn fixed(limit: usize) -> Self {
+        RequestLimiter { permits: Semaphore::new(limit), adaptive_limit: None }
+    }
+
+    /// Starts at `initial_limit` and moves between 1 and `max_limit` requests, by `increase_step` at a time
+    pub fn adaptive(target_p99_latency: Duration, initial_limit: usize, max_limit: usize, increase_step: usize) -> Self {
+        let max_limit = max_limit.max(1);
+        let limit = initial_limit.clamp(1, max_limit);
+        log::info!("Adapting the requests in flight to a p99 latency of {target_p99_latency:?}, from {limit} up to {max_limit}");
+
+        let adaptive_limit = AdaptiveLimit {
+            target_p99_latency,
+            min_limit: 1,
+            max_limit,
+            increase_step: increase_step.max(1),
+            limit,
+            permits_to_forget: 0,
+            window_start: Instant::now(),
+            latencies: Vec::new(),
+            last_overload: None,
+        };
+
+        RequestLimiter { permits: Semaphore::new(limit), adaptive_limit: Some(Mutex::new(adaptive_limit)) }
+    }
+
+    pub async fn acquire(&self) -> anyhow::Result<RequestPermit<'_>> {
+        let permit = self.permits.acquire().await?;
+        Ok(RequestPermit { request_limiter: self, permit: Some(permit) })
+    }
+
+    /// Gives back the permit of a request that took `latency` and ended with `result`, and adapts the limit
+    pub fn release<T>(&self, permit: RequestPermit<'_>, latency: Duration, result: &Result<T, QueryError>) {
+        if let Some(adaptive_limit) = &self.adaptive_limit {
+            let signal = match result {
+                Err(error) if is_overload(error) => Signal::Overload,
+                _ => Signal::Latency(latency),
+            };
+
+            adaptive_limit.lock().unwrap().record(signal, &self.permits);
+        }
+
+        // After recording, so that the permit is dropped when the limit shrank for this very request
+        drop(permit);
+    }
+}
+
+impl Drop for RequestPermit<'_> {
+    fn drop(&mut self) {
+        let (Some(permit), Some(adaptive_limit)) = (self.permit.take(), &self.request_limiter.adaptive_limit) else {
+            return;
+        };
+
+        let mut adaptive_limit = adaptive_limit.lock().unwrap();
+        if adaptive_limit.permits_to_forget > 0 {
+            adaptive_limit.permits_to_forget -= 1;
+            permit.forget();
+        }
+    }
+}
+
+impl AdaptiveLimit {
+    fn record(&mut self, signal: Signal, permits: &Semaphore) {
+        match signal {
+            // One decrease per window, so that the requests failing together count once
+            Signal::Overload => {
+                let decreased_lately = self.last_overload.is_some_and(|last_overload| last_overload.elapsed() < ADJUSTMENT_WINDOW);
+                if !decreased_lately {
+                    self.last_overload = Some(Instant::now());
+                    let limit = scale(self.limit, ERROR_DECREASE).max(self.min_limit);
+                    self.set_limit(limit, permits, "after a timeout or an overloaded coordinator".to_owned());
+                }
+            },
+            Signal::Latency(latency) => {
+                self.latencies.push(latency);
+                if self.window_start.elapsed() >= ADJUSTMENT_WINDOW {
+                    let p99_latency = p99(&mut self.latencies);
+                    if p99_latency <= self.target_p99_latency {
+                        let limit = (self.limit + self.increase_step).min(self.max_limit);
+                        self.set_limit(limit, permits, format!("with a p99 latency of {p99_latency:?}"));
+                    } else {
+                        let limit = scale(self.limit, LATENCY_DECREASE).max(self.min_limit);
+                        self.set_limit(limit, permits, format!("with a p99 latency of {p99_latency:?}"));
+                    }
+                }
+            },
+        }
+    }
+
+    /// Moves the limit and opens a new window
+    fn set_limit(&mut self, limit: usize, permits: &Semaphore, reason: String) {
+        if limit > self.limit {
+            let mut increase = limit - self.limit;
+            let forgiven = increase.min(self.permits_to_forget);
+            self.permits_to_forget -= forgiven;
+            increase -= forgiven;
+            permits.add_permits(increase);
+        } else {
+            for _ in limit..self.limit {
+                match permits.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => self.permits_to_forget += 1,
+                }
+            }
+        }
+
+        if limit != self.limit {
+            log::info!("Requests in flight limited from {previous} to {limit} {reason}", previous=self.limit);
+        }
+
+        self.limit = limit;
+        self.window_start = Instant::now();
+        self.latencies.clear();
+    }
+}
+
+/// Errors telling the cluster takes more requests than it can handle
+fn is_overload(error: &QueryError) -> bool {
+    matches!(RetryableError::of(error),
+             Some(RetryableError::WriteTimeout | RetryableError::Overloaded | RetryableError::RequestTimeout))
+}
+
+fn scale(limit: usize, factor: f64) -> usize {
+    (limit as f64 * factor) as usize
+}
+
+fn p99(latencies: &mut [Duration]) -> Duration {
+    latencies.sort_unstable();
+    let position = (latencies.len() * 99).div_ceil(100).saturating_sub(1);
+    latencies[position]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scylla::transport::errors::DbError;
+
+    use super::*;
+
+    const TARGET_LATENCY: Duration = Duration::from_millis(50);
+
+    fn fast() -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    fn overloaded() -> Result<(), QueryError> {
+        Err(QueryError::DbError(DbError::Overloaded, "Overloaded".to_owned()))
+    }
+
+    impl RequestLimiter {
+        fn limit(&self) -> usize {
+            self.adaptive_limit.as_ref().unwrap().lock().unwrap().limit
+        }
+
+        /// Ends the adjustment window and the error backoff, as if a window went by
+        fn end_window(&self) {
+            let mut adaptive_limit = self.adaptive_limit.as_ref().unwrap().lock().unwrap();
+            adaptive_limit.window_start -= ADJUSTMENT_WINDOW;
+            adaptive_limit.last_overload = adaptive_limit.last_overload.map(|last_overload| last_overload - ADJUSTMENT_WINDOW);
+        }
+
+        /// Sends a request taking `latency` and ending with `result`
+        async fn request(&self, latency: Duration, result: Result<(), QueryError>) {
+            let permit = self.acquire().await.unwrap();
+            self.release(permit, latency, &result);
+        }
+    }
+
+    #[tokio::test]
+    async fn grows_after_a_window_under_the_target_latency() {
+        let request_limiter = RequestLimiter::adaptive(TARGET_LATENCY, 4, 10, 4);
+
+        request_limiter.request(Duration::from_millis(10), fast()).await;
+        assert_eq!(request_limiter.limit(), 4);
+
+        request_limiter.end_window();
+        request_limiter.request(Duration::from_millis(10), fast()).await;
+        assert_eq!((request_limiter.limit(), request_limiter.permits.available_permits()), (8, 8));
+
+        request_limiter.end_window();
+        request_limiter.request(Duration::from_millis(10), fast()).await;
+        assert_eq!((request_limiter.limit(), request_limiter.permits.available_permits()), (10, 10));
+    }
+
+    #[tokio::test]
+    async fn shrinks_when_the_p99_latency_goes_over_the_target() {
+        let request_limiter = RequestLimiter::adaptive(TARGET_LATENCY, 20, 20, 1);
+
+        for _ in 0..98 {
+            request_limiter.request(Duration::from_millis(10), fast()).await;
+        }
+        request_limiter.request(Duration::from_millis(200), fast()).await;
+        request_limiter.end_window();
+        request_limiter.request(Duration::from_millis(200), fast()).await;
+
+        assert_eq!((request_limiter.limit(), request_limiter.permits.available_permits()), (18, 18));
+    }
+
+    #[tokio::test]
+    async fn halves_once_per_window_on_overload() {
+        let request_limiter = RequestLimiter::adaptive(TARGET_LATENCY, 16, 16, 1);
+
+        request_limiter.request(Duration::from_millis(10), overloaded()).await;
+        request_limiter.request(Duration::from_millis(10), overloaded()).await;
+        assert_eq!((request_limiter.limit(), request_limiter.permits.available_permits()), (8, 8));
+
+        request_limiter.end_window();
+        request_limiter.request(Duration::from_millis(10), overloaded()).await;
+        assert_eq!((request_limiter.limit(), request_limiter.permits.available_permits()), (4, 4));
+
+        for _ in 0..4 {
+            request_limiter.end_window();
+            request_limiter.request(Duration::from_millis(10), overloaded()).await;
+        }
+        assert_eq!(request_limiter.limit(), 1);
+    }
+
+    #[tokio::test]
+    async fn drops_the_permits_over_a_limit_shrunk_below_the_requests_in_flight() {
+        let request_limiter = RequestLimiter::adaptive(TARGET_LATENCY, 4, 4, 1);
+
+        let mut permits = Vec::new();
+        for _ in 0..4 {
+            permits.push(request_limiter.acquire().await.unwrap());
+        }
+
+        // Halved to 2 with the 4 permits in flight: the first 2 given back are dropped
+        request_limiter.release(permits.pop().unwrap(), Duration::from_millis(10), &overloaded());
+        assert_eq!(request_limiter.permits.available_permits(), 0);
+
+        // A request cancelled before it is released counts as well
+        drop(permits.pop());
+        assert_eq!(request_limiter.permits.available_permits(), 0);
+
+        drop(permits);
+        assert_eq!(request_limiter.permits.available_permits(), 2);
+        assert_eq!(request_limiter.limit(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_a_fixed_limit() {
+        let request_limiter = RequestLimiter::fixed(2);
+
+        let permit = request_limiter.acquire().await.unwrap();
+        request_limiter.release(permit, Duration::from_secs(10), &overloaded());
+        let _permit = request_limiter.acquire().await.unwrap();
+
+        assert_eq!(request_limiter.permits.available_permits(), 1);
+    }
+}
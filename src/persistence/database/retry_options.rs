@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use rand::Rng;
+use scylla::transport::errors::{DbError, QueryError};
+
+/// Classes of errors after which a write is attempted again
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryableError {
+    /// The replicas did not acknowledge the write in time
+    WriteTimeout,
+    /// Too few replicas were alive for the consistency level
+    Unavailable,
+    /// The coordinator was overloaded
+    Overloaded,
+    /// The coordinator was still bootstrapping
+    Bootstrapping,
+    /// No response came within the request timeout
+    RequestTimeout,
+    /// The connection failed or ran out of streams
+    Connection,
+}
+
+impl RetryableError {
+    pub(super) fn of(error: &QueryError) -> Option<RetryableError> {
+        let retryable_error = match error {
+            QueryError::DbError(DbError::WriteTimeout { .. }, _) => RetryableError::WriteTimeout,
+            QueryError::DbError(DbError::Unavailable { .. }, _) => RetryableError::Unavailable,
+            QueryError::DbError(DbError::Overloaded, _) => RetryableError::Overloaded,
+            QueryError::DbError(DbError::IsBootstrapping, _) => RetryableError::Bootstrapping,
+            QueryError::RequestTimeout(_) | QueryError::TimeoutError => RetryableError::RequestTimeout,
+            QueryError::IoError(_) | QueryError::UnableToAllocStreamId | QueryError::TooManyOrphanedStreamIds(_) => RetryableError::Connection,
+            _ => return None,
+        };
+
+        Some(retryable_error)
+    }
+}
+
+/// How failed writes are attempted again. The inserts are idempotent, so writing a row twice is harmless.
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+    /// Attempts of each write, the first one included
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub retryable_errors: Vec<RetryableError>,
+}
+
+impl RetryOptions {
+    /// Whether a write that failed with `error` on attempt `attempt` (counted from 1) is attempted again
+    pub fn should_retry(&self, error: &QueryError, attempt: u32) -> bool {
+        attempt < self.max_attempts
+            && RetryableError::of(error).is_some_and(|retryable_error| self.retryable_errors.contains(&retryable_error))
+    }
+
+    /// Time to wait after attempt `attempt`: a random time up to the initial backoff doubled on each attempt,
+    /// capped at the maximum backoff, so that writes failing together do not retry together
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential_backoff = self.initial_backoff.saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)));
+        let backoff = exponential_backoff.min(self.max_backoff);
+
+        backoff.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_options(retryable_errors: Vec<RetryableError>) -> RetryOptions {
+        RetryOptions {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            retryable_errors,
+        }
+    }
+
+    #[test]
+    fn retries_the_enabled_errors_until_the_last_attempt() {
+        let retry_options = retry_options(vec![RetryableError::Overloaded, RetryableError::RequestTimeout]);
+        let overloaded = QueryError::DbError(DbError::Overloaded, "Overloaded".to_owned());
+
+        assert!(retry_options.should_retry(&overloaded, 1));
+        assert!(retry_options.should_retry(&overloaded, 2));
+        assert!(!retry_options.should_retry(&overloaded, 3));
+        assert!(retry_options.should_retry(&QueryError::TimeoutError, 1));
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let retry_options = retry_options(vec![RetryableError::Overloaded]);
+
+        assert!(!retry_options.should_retry(&QueryError::DbError(DbError::IsBootstrapping, String::new()), 1));
+        assert!(!retry_options.should_retry(&QueryError::DbError(DbError::SyntaxError, String::new()), 1));
+        assert!(!retry_options.should_retry(&QueryError::TimeoutError, 1));
+        assert_eq!(RetryableError::of(&QueryError::DbError(DbError::Invalid, String::new())), None);
+    }
+
+    #[test]
+    fn backs_off_up_to_the_doubled_initial_backoff_capped_at_the_maximum() {
+        let retry_options = retry_options(vec![]);
+
+        for _ in 0..100 {
+            assert!(retry_options.backoff(1) <= Duration::from_millis(100));
+            assert!(retry_options.backoff(2) <= Duration::from_millis(200));
+            assert!(retry_options.backoff(3) <= Duration::from_millis(300));
+            assert!(retry_options.backoff(u32::MAX) <= Duration::from_millis(300));
+        }
+
+        let backoffs = (0..100).map(|_| retry_options.backoff(3)).collect::<Vec<_>>();
+        assert!(backoffs.iter().any(|backoff| *backoff > Duration::from_millis(200)), "Expected backoffs past the previous attempt");
+        assert!(backoffs.windows(2).any(|pair| pair[0] != pair[1]), "Expected jittered backoffs");
+    }
+}
@@ -0,0 +1,92 @@
+use std::{num::NonZeroUsize, time::Duration};
+
+use scylla::{statement::{Consistency, SerialConsistency}, transport::Compression};
+
+use super::tls_options::TlsOptions;
+
+/// Consistency level of the inserts
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ConsistencyLevel {
+    Any,
+    One,
+    Two,
+    Three,
+    Quorum,
+    All,
+    LocalQuorum,
+    EachQuorum,
+    LocalOne,
+}
+
+impl From<ConsistencyLevel> for Consistency {
+    fn from(consistency_level: ConsistencyLevel) -> Self {
+        match consistency_level {
+            ConsistencyLevel::Any => Consistency::Any,
+            ConsistencyLevel::One => Consistency::One,
+            ConsistencyLevel::Two => Consistency::Two,
+            ConsistencyLevel::Three => Consistency::Three,
+            ConsistencyLevel::Quorum => Consistency::Quorum,
+            ConsistencyLevel::All => Consistency::All,
+            ConsistencyLevel::LocalQuorum => Consistency::LocalQuorum,
+            ConsistencyLevel::EachQuorum => Consistency::EachQuorum,
+            ConsistencyLevel::LocalOne => Consistency::LocalOne,
+        }
+    }
+}
+
+/// Consistency level of the Paxos phase of conditional inserts
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SerialConsistencyLevel {
+    Serial,
+    LocalSerial,
+}
+
+impl From<SerialConsistencyLevel> for SerialConsistency {
+    fn from(serial_consistency_level: SerialConsistencyLevel) -> Self {
+        match serial_consistency_level {
+            SerialConsistencyLevel::Serial => SerialConsistency::Serial,
+            SerialConsistencyLevel::LocalSerial => SerialConsistency::LocalSerial,
+        }
+    }
+}
+
+/// Compression of the frames exchanged with the nodes
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCompression {
+    Lz4,
+    Snappy,
+}
+
+impl From<FrameCompression> for Compression {
+    fn from(frame_compression: FrameCompression) -> Self {
+        match frame_compression {
+            FrameCompression::Lz4 => Compression::Lz4,
+            FrameCompression::Snappy => Compression::Snappy,
+        }
+    }
+}
+
+/// How the session connects to the cluster, and the defaults of its requests
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub consistency: ConsistencyLevel,
+    pub serial_consistency: Option<SerialConsistencyLevel>,
+    pub request_timeout: Duration,
+    pub connection_timeout: Duration,
+    /// Encrypts the connections when set
+    pub tls_options: Option<TlsOptions>,
+    /// Datacenter the requests are sent to, leaving the others out, when set
+    pub local_datacenter: Option<String>,
+    /// Rack of the local datacenter whose replicas are tried first
+    pub preferred_rack: Option<String>,
+    /// Whether requests go to the replicas of their partition
+    pub token_aware: bool,
+    /// Whether the connections are opened to each shard of the nodes, through the shard-aware port, or to the
+    /// nodes as a whole
+    pub shard_aware: bool,
+    /// Connections to each shard, or to each node when not shard aware
+    pub connections_per_shard: NonZeroUsize,
+    pub compression: Option<FrameCompression>,
+}
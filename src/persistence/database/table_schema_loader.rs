@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use scylla::Session;
+
+use crate::entities::{ColumnType, TableSchema};
+
+
+pub async fn load_table_schema(session: &Session, keyspace_name: &str, table_name: &str) -> anyhow::Result<TableSchema> {
+    let query = "SELECT column_name, type, kind, position FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?";
+    let rows =
+        session
+            .query(query, (keyspace_name, table_name))
+            .await?
+            .rows_typed::<(String, String, String, i32)>()?
+            .collect::<Result<Vec<_>, _>>()?;
+
+    if rows.is_empty() {
+        anyhow::bail!("Table {keyspace_name}.{table_name} was not found in system_schema.columns")
+    }
+
+    log::info!("Loaded {total} columns of table {keyspace_name}.{table_name}", total=rows.len());
+
+    table_schema_of(rows)
+}
+
+/// Builds the schema from the `column_name, type, kind, position` rows of the table columns, ordering the
+/// partition key columns by their position in the key
+fn table_schema_of(rows: Vec<(String, String, String, i32)>) -> anyhow::Result<TableSchema> {
+    let mut columns = HashMap::new();
+    let mut partition_key = Vec::new();
+
+    for (column_name, type_string, kind, position) in rows {
+        let column_type: ColumnType = type_string.parse()?;
+
+        if kind == "partition_key" {
+            partition_key.push((position, column_name.clone()));
+        }
+        columns.insert(column_name, column_type);
+    }
+
+    partition_key.sort();
+    let partition_key = partition_key.into_iter().map(|(_, column_name)| column_name).collect();
+
+    Ok(TableSchema::new(columns, partition_key))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(column_name: &str, type_string: &str, kind: &str, position: i32) -> (String, String, String, i32) {
+        (column_name.to_owned(), type_string.to_owned(), kind.to_owned(), position)
+    }
+
+    #[test]
+    fn orders_the_partition_key_by_position() {
+        let table_schema = table_schema_of(vec![
+            column("user_id", "uuid", "partition_key", 1),
+            column("name", "text", "regular", -1),
+            column("tenant", "text", "partition_key", 0),
+            column("created_at", "timestamp", "clustering", 0),
+        ]).unwrap();
+
+        assert_eq!(table_schema.partition_key(), ["tenant", "user_id"]);
+        assert_eq!(table_schema.column_names().count(), 4);
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use openssl::{ssl::{SslContext, SslContextBuilder, SslFiletype, SslMethod, SslVerifyMode}, x509::verify::X509CheckFlags};
+
+/// How connections to the nodes are encrypted
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    /// PEM certificates of the authorities trusted to sign the node certificates, the system ones when unset
+    pub ca_file: Option<PathBuf>,
+    /// PEM certificate chain and private key presented to the nodes, for clusters requiring client certificates
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    /// Whether the node certificates are verified at all
+    pub verify: bool,
+    /// Host name the node certificates must match. The driver builds the TLS session of every connection from
+    /// one context, without the address of its node, so all the nodes are matched to this one name.
+    pub verify_hostname: Option<String>,
+}
+
+impl TlsOptions {
+    pub fn ssl_context(&self) -> anyhow::Result<SslContext> {
+        let mut context_builder = SslContextBuilder::new(SslMethod::tls_client())?;
+
+        match &self.ca_file {
+            Some(ca_file) => context_builder.set_ca_file(ca_file)
+                .with_context(|| format!("Cannot read CA file {}", ca_file.display()))?,
+            None => context_builder.set_default_verify_paths()?,
+        }
+
+        if let Some(cert_file) = &self.cert_file {
+            context_builder.set_certificate_chain_file(cert_file)
+                .with_context(|| format!("Cannot read certificate file {}", cert_file.display()))?;
+        }
+
+        if let Some(key_file) = &self.key_file {
+            context_builder.set_private_key_file(key_file, SslFiletype::PEM)
+                .with_context(|| format!("Cannot read key file {}", key_file.display()))?;
+            context_builder.check_private_key().context("The private key does not match the certificate")?;
+        }
+
+        context_builder.set_verify(if self.verify { SslVerifyMode::PEER } else { SslVerifyMode::NONE });
+
+        if let Some(verify_hostname) = &self.verify_hostname {
+            let verify_param = context_builder.verify_param_mut();
+            verify_param.set_hostflags(X509CheckFlags::NO_PARTIAL_WILDCARDS);
+            verify_param.set_host(verify_hostname)
+                .with_context(|| format!("Invalid host name to verify {verify_hostname:?}"))?;
+        }
+
+        Ok(context_builder.build())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::{TcpListener, TcpStream}};
+
+    use openssl::{asn1::Asn1Time, bn::BigNum, hash::MessageDigest, pkey::{PKey, Private}, rsa::Rsa,
+                  ssl::{Ssl, SslAcceptor}, x509::{X509, X509NameBuilder, extension::SubjectAlternativeName}};
+
+    use super::*;
+
+    /// A certificate for `host_name`, signed by `issuer` or else self-signed
+    fn certificate(host_name: &str, issuer: Option<(&X509, &PKey<Private>)>) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", host_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(rand::random()).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+
+        match issuer {
+            Some((issuer_certificate, issuer_key)) => {
+                let alternative_name = SubjectAlternativeName::new().dns(host_name).build(&builder.x509v3_context(Some(issuer_certificate), None)).unwrap();
+                builder.append_extension(alternative_name).unwrap();
+                builder.set_issuer_name(issuer_certificate.subject_name()).unwrap();
+                builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            },
+            None => {
+                builder.append_extension(openssl::x509::extension::BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+                builder.set_issuer_name(&name).unwrap();
+                builder.sign(&key, MessageDigest::sha256()).unwrap();
+            },
+        }
+
+        (builder.build(), key)
+    }
+
+    /// Whether a client with `tls_options` completes a handshake with a node presenting `node_certificate`
+    fn handshake(tls_options: &TlsOptions, node_certificate: &(X509, PKey<Private>)) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+        acceptor.set_certificate(&node_certificate.0).unwrap();
+        acceptor.set_private_key(&node_certificate.1).unwrap();
+        let acceptor = acceptor.build();
+
+        let node = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = acceptor.accept(stream);
+        });
+
+        // As the driver does, a session of the shared context without a host name of its own
+        let ssl = Ssl::new(&tls_options.ssl_context().unwrap()).unwrap();
+        let connected = ssl.connect(TcpStream::connect(address).unwrap()).is_ok();
+        node.join().unwrap();
+
+        connected
+    }
+
+    #[test]
+    fn verifies_node_certificates() {
+        let authority = certificate("Test authority", None);
+        let mut ca_file = tempfile::NamedTempFile::new().unwrap();
+        ca_file.write_all(&authority.0.to_pem().unwrap()).unwrap();
+
+        let node_certificate = certificate("node1.cluster.example.com", Some((&authority.0, &authority.1)));
+        let untrusted_certificate = certificate("node1.cluster.example.com", None);
+
+        let tls_options = |verify, verify_hostname: Option<&str>| TlsOptions {
+            ca_file: Some(ca_file.path().to_owned()),
+            cert_file: None,
+            key_file: None,
+            verify,
+            verify_hostname: verify_hostname.map(str::to_owned),
+        };
+
+        assert!(handshake(&tls_options(true, None), &node_certificate));
+        assert!(!handshake(&tls_options(true, None), &untrusted_certificate));
+        assert!(handshake(&tls_options(false, None), &untrusted_certificate));
+
+        assert!(handshake(&tls_options(true, Some("node1.cluster.example.com")), &node_certificate));
+        assert!(!handshake(&tls_options(true, Some("node2.cluster.example.com")), &node_certificate));
+        assert!(!handshake(&tls_options(true, Some("node1.cluster.example.com")), &untrusted_certificate));
+    }
+
+    #[test]
+    fn matches_wildcard_certificates() {
+        let authority = certificate("Test authority", None);
+        let mut ca_file = tempfile::NamedTempFile::new().unwrap();
+        ca_file.write_all(&authority.0.to_pem().unwrap()).unwrap();
+
+        let node_certificate = certificate("*.cluster.example.com", Some((&authority.0, &authority.1)));
+        let tls_options = |verify_hostname: &str| TlsOptions {
+            ca_file: Some(ca_file.path().to_owned()),
+            cert_file: None,
+            key_file: None,
+            verify: true,
+            verify_hostname: Some(verify_hostname.to_owned()),
+        };
+
+        assert!(handshake(&tls_options("node1.cluster.example.com"), &node_certificate));
+        assert!(!handshake(&tls_options("node1.other.example.com"), &node_certificate));
+    }
+}
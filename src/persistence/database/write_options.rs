@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use super::{column_binding::ColumnBinding, retry_options::RetryOptions};
+
+/// Requests in flight allowed for each connection when no total is given
+pub const DEFAULT_REQUESTS_PER_CONNECTION: usize = 128;
+
+/// How the rows of a batch are sent to the database
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// One insert per row
+    Rows,
+    /// UNLOGGED batches of the rows sharing a partition key
+    UnloggedBatches,
+}
+
+/// How rows are bound and written to the table
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub column_binding: ColumnBinding,
+    pub write_mode: WriteMode,
+    /// Most rows in an UNLOGGED batch
+    pub max_batch_rows: usize,
+    /// Most bytes of values in an UNLOGGED batch, so batches stay under the size Scylla warns about
+    pub max_batch_bytes: usize,
+    /// Most requests in flight over all the connections of the session, or `DEFAULT_REQUESTS_PER_CONNECTION`
+    /// for each connection counted when the session opens
+    pub max_requests_in_flight: Option<usize>,
+    /// When set, the requests in flight adapt to keep their p99 latency under it, up to `max_requests_in_flight`
+    pub target_p99_latency: Option<Duration>,
+    pub retry_options: RetryOptions,
+}
@@ -0,0 +1,246 @@
+use std::{cell::Cell, rc::Rc};
+
+use anyhow::Context;
+use apache_avro::{Reader, schema::{NamesRef, ResolvedSchema, Schema}, types::Value as AvroValue};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use num_bigint::BigInt;
+use serde_json::{Map, Value};
+use tokio::{io::AsyncRead, sync::mpsc, task::JoinHandle};
+use tokio_util::io::SyncIoBridge;
+
+use super::{json_value::{bytes_to_json, date_time_to_json, float_to_json}, source_record::SourceRecord};
+
+/// Streams the records of an Avro object container file. The decoder is synchronous, so it runs on a
+/// blocking thread and hands the records over a bounded channel.
+pub struct AvroReader {
+    receiver: mpsc::Receiver<anyhow::Result<SourceRecord>>,
+    /// The decoding thread, awaited once the channel closes so that a panic does not pass for the end of the file
+    decoder: Option<JoinHandle<()>>,
+}
+
+impl AvroReader {
+    pub fn new<R: AsyncRead + Unpin + Send + 'static>(reader: R) -> Self {
+        let (sender, receiver) = mpsc::channel(1024);
+        let bridge = SyncIoBridge::new(reader);
+
+        let decoder = tokio::task::spawn_blocking(move || {
+            if let Err(error) = read_avro_records(bridge, &sender) {
+                let _ = sender.blocking_send(Err(error));
+            }
+        });
+
+        AvroReader { receiver, decoder: Some(decoder) }
+    }
+
+    pub async fn next_record(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        if let Some(record) = self.receiver.recv().await {
+            return record.map(Some);
+        }
+
+        if let Some(decoder) = self.decoder.take() {
+            decoder.await.context("The Avro decoder failed")?;
+        }
+
+        Ok(None)
+    }
+}
+
+
+/// Decoding errors end the file, while records that cannot be converted to JSON are sent as unreadable
+fn read_avro_records<R: std::io::Read>(reader: R, sender: &mpsc::Sender<anyhow::Result<SourceRecord>>) -> anyhow::Result<()> {
+    let bytes_read = Rc::new(Cell::new(0));
+    let avro_reader = Reader::new(CountingReader { reader, bytes_read: bytes_read.clone() }).context("Invalid Avro header")?;
+    let writer_schema = avro_reader.writer_schema().clone();
+    let resolved_schema = ResolvedSchema::try_from(&writer_schema)?;
+
+    log::info!("Avro writer schema: {schema}", schema=writer_schema.canonical_form());
+
+    let mut previous_bytes_read = bytes_read.get();
+
+    for (record_index, avro_value) in avro_reader.enumerate() {
+        let record_number = record_index as u64 + 1;
+        let avro_value = avro_value.with_context(|| format!("Avro record {record_number}"))?;
+
+        let mut record = match avro_value_to_json(&avro_value, &writer_schema, resolved_schema.get_names()) {
+            Ok(value) => SourceRecord::new(record_number, value),
+            Err(error) => SourceRecord::unreadable(record_number, format!("{avro_value:?}"), error.context(format!("Avro record {record_number}"))),
+        };
+        record.byte_length = bytes_read.get() - previous_bytes_read;
+        previous_bytes_read = bytes_read.get();
+
+        if sender.blocking_send(Ok(record)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the bytes read through it, shared with the decoder that owns it
+struct CountingReader<R> {
+    reader: R,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buffer)?;
+        self.bytes_read.set(self.bytes_read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Converts an Avro value to JSON, using the writer schema to resolve named types and decimal scales.
+/// Logical dates, times and timestamps become their ISO 8601 forms, to be parsed after the column types.
+fn avro_value_to_json(value: &AvroValue, schema: &Schema, names: &NamesRef) -> anyhow::Result<Value> {
+    if let Schema::Ref { name } = schema {
+        let named_schema = names.get(name).with_context(|| format!("Unknown Avro type {name}"))?;
+        return avro_value_to_json(value, named_schema, names);
+    }
+
+    let json_value = match (value, schema) {
+        (AvroValue::Union(index, inner_value), Schema::Union(union_schema)) => {
+            let variant_schema = union_schema.variants().get(*index as usize)
+                .with_context(|| format!("Invalid Avro union branch {index}"))?;
+            avro_value_to_json(inner_value, variant_schema, names)?
+        },
+        (AvroValue::Null, _) => Value::Null,
+        (AvroValue::Boolean(bool_value), _) => Value::Bool(*bool_value),
+        (AvroValue::Int(int_value), _) => Value::from(*int_value),
+        (AvroValue::Long(long_value), _) => Value::from(*long_value),
+        (AvroValue::Float(float_value), _) => float_to_json(f64::from(*float_value)),
+        (AvroValue::Double(double_value), _) => float_to_json(*double_value),
+        (AvroValue::Bytes(bytes) | AvroValue::Fixed(_, bytes), _) => bytes_to_json(bytes),
+        (AvroValue::String(string_value) | AvroValue::Enum(_, string_value), _) => Value::String(string_value.to_owned()),
+        (AvroValue::Uuid(uuid), _) => Value::String(uuid.to_string()),
+        (AvroValue::Array(items), Schema::Array(array_schema)) => {
+            let items = items.iter()
+                .map(|item| avro_value_to_json(item, &array_schema.items, names))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Value::Array(items)
+        },
+        (AvroValue::Map(entries), Schema::Map(map_schema)) => {
+            let entries = entries.iter()
+                .map(|(key, entry)| Ok((key.to_owned(), avro_value_to_json(entry, &map_schema.types, names)?)))
+                .collect::<anyhow::Result<Map<_, _>>>()?;
+            Value::Object(entries)
+        },
+        (AvroValue::Record(fields), Schema::Record(record_schema)) => {
+            let fields = fields.iter().map(|(name, field_value)| {
+                let field_schema = record_schema.lookup.get(name)
+                    .and_then(|position| record_schema.fields.get(*position))
+                    .with_context(|| format!("Unknown field {name} in Avro record {}", record_schema.name))?;
+                Ok((name.to_owned(), avro_value_to_json(field_value, &field_schema.schema, names)?))
+            }).collect::<anyhow::Result<Map<_, _>>>()?;
+            Value::Object(fields)
+        },
+        (AvroValue::Decimal(decimal), Schema::Decimal(decimal_schema)) => {
+            let unscaled_bytes = Vec::<u8>::try_from(decimal)?;
+            let unscaled = BigInt::from_signed_bytes_be(&unscaled_bytes);
+            Value::String(BigDecimal::new(unscaled, decimal_schema.scale as i64).to_string())
+        },
+        (AvroValue::BigDecimal(decimal), _) => Value::String(decimal.to_string()),
+        (AvroValue::Date(days), _) => {
+            let date = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + chrono::Duration::days(i64::from(*days));
+            Value::String(date.format("%Y-%m-%d").to_string())
+        },
+        (AvroValue::TimeMillis(milliseconds), _) => time_to_json(i64::from(*milliseconds) * 1_000_000)?,
+        (AvroValue::TimeMicros(microseconds), _) => time_to_json(microseconds * 1_000)?,
+        (AvroValue::TimestampMillis(milliseconds) | AvroValue::LocalTimestampMillis(milliseconds), _) =>
+            timestamp_to_json(DateTime::from_timestamp_millis(*milliseconds))?,
+        (AvroValue::TimestampMicros(microseconds) | AvroValue::LocalTimestampMicros(microseconds), _) =>
+            timestamp_to_json(DateTime::from_timestamp_micros(*microseconds))?,
+        (AvroValue::TimestampNanos(nanoseconds) | AvroValue::LocalTimestampNanos(nanoseconds), _) =>
+            timestamp_to_json(Some(DateTime::from_timestamp_nanos(*nanoseconds)))?,
+        (AvroValue::Duration(duration), _) => {
+            let (months, days, milliseconds) = (u32::from(duration.months()), u32::from(duration.days()), u32::from(duration.millis()));
+            Value::String(format!("{months}mo{days}d{milliseconds}ms"))
+        },
+        (value, schema) => anyhow::bail!("Avro value {value:?} does not match its schema {}", schema.canonical_form()),
+    };
+
+    Ok(json_value)
+}
+
+fn time_to_json(nanoseconds: i64) -> anyhow::Result<Value> {
+    let seconds = u32::try_from(nanoseconds / 1_000_000_000)?;
+    let nanoseconds = u32::try_from(nanoseconds % 1_000_000_000)?;
+    let time = NaiveTime::from_num_seconds_from_midnight_opt(seconds, nanoseconds)
+        .with_context(|| format!("Invalid Avro time {seconds}s"))?;
+
+    Ok(Value::String(time.format("%H:%M:%S%.f").to_string()))
+}
+
+fn timestamp_to_json(date_time: Option<DateTime<chrono::Utc>>) -> anyhow::Result<Value> {
+    let date_time = date_time.context("Avro timestamp is out of range")?;
+    Ok(date_time_to_json(date_time.naive_utc()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::{Decimal, Writer, types::Record};
+    use serde_json::json;
+
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "order",
+        "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "note", "type": ["null", "string"]},
+            {"name": "payload", "type": "bytes"},
+            {"name": "status", "type": {"type": "enum", "name": "status", "symbols": ["OPEN", "CLOSED"]}},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "counts", "type": {"type": "map", "values": "int"}},
+            {"name": "day", "type": {"type": "int", "logicalType": "date"}},
+            {"name": "at", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "price", "type": {"type": "bytes", "logicalType": "decimal", "precision": 9, "scale": 2}},
+            {"name": "previous", "type": ["null", "order"]}
+        ]
+    }"#;
+
+    #[tokio::test]
+    async fn reads_the_records_of_an_avro_file() {
+        let schema = Schema::parse_str(SCHEMA).unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+
+        for id in [1, 2] {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("id", AvroValue::Long(id));
+            record.put("note", AvroValue::Union(1, Box::new(AvroValue::String("first".to_owned()))));
+            record.put("payload", AvroValue::Bytes(vec![0xca, 0xfe]));
+            record.put("status", AvroValue::Enum(1, "CLOSED".to_owned()));
+            record.put("tags", AvroValue::Array(vec![AvroValue::String("a".to_owned())]));
+            record.put("counts", AvroValue::Map([("x".to_owned(), AvroValue::Int(3))].into()));
+            record.put("day", AvroValue::Date(19723));
+            record.put("at", AvroValue::TimestampMillis(1_704_103_200_500));
+            record.put("price", AvroValue::Decimal(Decimal::from(12345_i32.to_be_bytes().to_vec())));
+            record.put("previous", AvroValue::Union(0, Box::new(AvroValue::Null)));
+            writer.append(record).unwrap();
+        }
+
+        let mut avro_reader = AvroReader::new(std::io::Cursor::new(writer.into_inner().unwrap()));
+
+        let mut records = Vec::new();
+        while let Some(record) = avro_reader.next_record().await.unwrap() {
+            records.push((record.line_number, record.value.unwrap()));
+        }
+
+        let expected = |id| json!({
+            "id": id, "note": "first", "payload": "cafe", "status": "CLOSED", "tags": ["a"], "counts": {"x": 3},
+            "day": "2024-01-01", "at": "2024-01-01T10:00:00.500Z", "price": "123.45", "previous": null,
+        });
+        assert_eq!(records, [(1, expected(1)), (2, expected(2))]);
+    }
+
+    #[tokio::test]
+    async fn fails_files_that_are_not_avro() {
+        let mut avro_reader = AvroReader::new(&b"{\"id\": 1}\n"[..]);
+
+        let error = avro_reader.next_record().await.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid Avro header");
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use aws_sdk_s3::types::{ByteStream, SdkError};
+
+use crate::entities::Checkpoint;
+
+use super::{is_s3_path, s3_client_for, s3_dataset::split_bucket_and_key};
+
+/// Keeps the checkpoint of a run in a local file or an S3 object
+pub struct CheckpointStore {
+    checkpoint_path: String,
+    s3_client: Option<aws_sdk_s3::Client>,
+}
+
+impl CheckpointStore {
+    pub fn new(checkpoint_path: &str, s3_client: Option<&aws_sdk_s3::Client>) -> Self {
+        CheckpointStore {
+            checkpoint_path: checkpoint_path.to_owned(),
+            s3_client: s3_client.cloned(),
+        }
+    }
+
+    /// Reads the saved checkpoint, if there is one yet
+    pub async fn load(&self) -> anyhow::Result<Option<Checkpoint>> {
+        let text = if is_s3_path(&self.checkpoint_path) {
+            self.load_s3().await?
+        } else {
+            match tokio::fs::read_to_string(&self.checkpoint_path).await {
+                Ok(text) => Some(text),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+                Err(error) => return Err(error.into()),
+            }
+        };
+
+        text.map(|text| Checkpoint::from_json(&text))
+            .transpose()
+            .with_context(|| format!("Cannot read checkpoint {}", self.checkpoint_path))
+    }
+
+    /// Replaces the saved checkpoint. Local files are written aside and renamed, so an interruption
+    /// never leaves a partial checkpoint behind
+    pub async fn save(&self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let text = checkpoint.to_json();
+
+        if is_s3_path(&self.checkpoint_path) {
+            let (bucket, key) = split_bucket_and_key(&self.checkpoint_path)?;
+            let s3_client = s3_client_for(self.s3_client.as_ref(), &self.checkpoint_path)?;
+
+            s3_client
+                .put_object()
+                .bucket(&bucket)
+                .key(&key)
+                .body(ByteStream::from(text.into_bytes()))
+                .send()
+                .await
+                .with_context(|| format!("Cannot save checkpoint {}", self.checkpoint_path))?;
+        } else {
+            let staging_path = PathBuf::from(format!("{}.tmp", self.checkpoint_path));
+            tokio::fs::write(&staging_path, text).await
+                .with_context(|| format!("Cannot save checkpoint {}", staging_path.display()))?;
+            tokio::fs::rename(&staging_path, &self.checkpoint_path).await
+                .with_context(|| format!("Cannot save checkpoint {}", self.checkpoint_path))?;
+        }
+
+        log::debug!("Saved checkpoint {checkpoint_path}", checkpoint_path=self.checkpoint_path);
+
+        Ok(())
+    }
+
+    async fn load_s3(&self) -> anyhow::Result<Option<String>> {
+        let (bucket, key) = split_bucket_and_key(&self.checkpoint_path)?;
+        let s3_client = s3_client_for(self.s3_client.as_ref(), &self.checkpoint_path)?;
+
+        let response = match s3_client.get_object().bucket(&bucket).key(&key).send().await {
+            Ok(response) => response,
+            Err(SdkError::ServiceError(context)) if context.err().is_no_such_key() => return Ok(None),
+            Err(error) => return Err(error).with_context(|| format!("Cannot open {}", self.checkpoint_path)),
+        };
+
+        let bytes = response.body.collect().await?.into_bytes();
+        Ok(Some(String::from_utf8(bytes.to_vec())?))
+    }
+}
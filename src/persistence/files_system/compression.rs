@@ -0,0 +1,175 @@
+use std::{io::Cursor, path::Path};
+
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, XzDecoder, ZstdDecoder};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+
+/// Reader over the decompressed bytes of a source file
+pub type SourceReader = Box<dyn AsyncBufRead + Unpin + Send>;
+
+/// Length of the longest magic bytes, those of xz
+const MAGIC_BYTES_LENGTH: usize = 6;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Detect from the file extension, or from the first bytes of the file
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+/// Wraps `reader` with the decoder of `compression`, detecting it when set to `Auto`. Returns the compression
+/// found along with the reader.
+pub async fn decompress<R: AsyncBufRead + Unpin + Send + 'static>(mut reader: R, source_path: &str, compression: Compression) -> anyhow::Result<(SourceReader, Compression)> {
+    let (reader, compression): (SourceReader, Compression) = match compression {
+        Compression::Auto => match compression_from_extension(source_path) {
+            Some(compression) => (Box::new(reader), compression),
+            None => {
+                let first_bytes = read_first_bytes(&mut reader, MAGIC_BYTES_LENGTH).await?;
+                let compression = compression_from_magic_bytes(&first_bytes);
+                (Box::new(Cursor::new(first_bytes).chain(reader)), compression)
+            },
+        },
+        compression => (Box::new(reader), compression),
+    };
+
+    if compression != Compression::None {
+        log::info!("Decompressing {source_path} as {compression:?}");
+    }
+
+    let source_reader: SourceReader = match compression {
+        Compression::Auto | Compression::None => Box::new(reader),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        },
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        },
+        Compression::Bzip2 => {
+            let mut decoder = BzDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        },
+        Compression::Xz => {
+            let mut decoder = XzDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        },
+    };
+
+    Ok((source_reader, compression))
+}
+
+
+/// Reads up to `length` bytes, fewer only at the end of the file, however little each read returns
+async fn read_first_bytes<R: AsyncBufRead + Unpin>(reader: &mut R, length: usize) -> anyhow::Result<Vec<u8>> {
+    let mut first_bytes = Vec::with_capacity(length);
+
+    while first_bytes.len() < length {
+        let input = reader.fill_buf().await?;
+        if input.is_empty() {
+            break;
+        }
+
+        let read = input.len().min(length - first_bytes.len());
+        first_bytes.extend_from_slice(&input[..read]);
+        reader.consume(read);
+    }
+
+    Ok(first_bytes)
+}
+
+fn compression_from_extension(source_path: &str) -> Option<Compression> {
+    let extension = Path::new(source_path).extension()?.to_str()?.to_lowercase();
+
+    match extension.as_str() {
+        "gz" | "gzip" => Some(Compression::Gzip),
+        "zst" | "zstd" => Some(Compression::Zstd),
+        "bz2" => Some(Compression::Bzip2),
+        "xz" => Some(Compression::Xz),
+        _ => None,
+    }
+}
+
+fn compression_from_magic_bytes(first_bytes: &[u8]) -> Compression {
+    if first_bytes.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if first_bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if first_bytes.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else if first_bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Compression::Xz
+    } else {
+        Compression::None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::bufread::{BzEncoder, GzipEncoder, XzEncoder, ZstdEncoder};
+
+    use super::*;
+
+    const TEXT: &[u8] = b"{\"id\": 1}\n{\"id\": 2}\n";
+
+    async fn compress(compression: Compression) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        match compression {
+            Compression::Gzip => GzipEncoder::new(TEXT).read_to_end(&mut compressed).await,
+            Compression::Zstd => ZstdEncoder::new(TEXT).read_to_end(&mut compressed).await,
+            Compression::Bzip2 => BzEncoder::new(TEXT).read_to_end(&mut compressed).await,
+            Compression::Xz => XzEncoder::new(TEXT).read_to_end(&mut compressed).await,
+            Compression::Auto | Compression::None => return TEXT.to_vec(),
+        }.unwrap();
+        compressed
+    }
+
+    async fn read_decompressed(compressed: Vec<u8>, source_path: &str, capacity: usize) -> (Vec<u8>, Compression) {
+        let reader = BufReader::with_capacity(capacity, Cursor::new(compressed));
+        let (mut source_reader, compression) = decompress(reader, source_path, Compression::Auto).await.unwrap();
+
+        let mut decompressed = Vec::new();
+        source_reader.read_to_end(&mut decompressed).await.unwrap();
+        (decompressed, compression)
+    }
+
+    #[tokio::test]
+    async fn detects_each_codec_from_its_magic_bytes_read_a_byte_at_a_time() {
+        for expected_compression in [Compression::Gzip, Compression::Zstd, Compression::Bzip2, Compression::Xz, Compression::None] {
+            for capacity in [1, 8192] {
+                let (decompressed, compression) = read_decompressed(compress(expected_compression).await, "data", capacity).await;
+
+                assert_eq!(compression, expected_compression, "Buffer of {capacity} bytes");
+                assert_eq!(decompressed, TEXT, "{expected_compression:?} with a buffer of {capacity} bytes");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_each_codec_from_the_extension() {
+        for (extension, expected_compression) in [("gz", Compression::Gzip), ("zst", Compression::Zstd), ("bz2", Compression::Bzip2), ("XZ", Compression::Xz)] {
+            let (decompressed, compression) = read_decompressed(compress(expected_compression).await, &format!("data.json.{extension}"), 1).await;
+
+            assert_eq!(compression, expected_compression);
+            assert_eq!(decompressed, TEXT);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_files_shorter_than_the_magic_bytes() {
+        for text in [&b""[..], b"1", b"\x1f"] {
+            let (decompressed, compression) = read_decompressed(text.to_vec(), "data", 1).await;
+
+            assert_eq!(compression, Compression::None);
+            assert_eq!(decompressed, text);
+        }
+    }
+}
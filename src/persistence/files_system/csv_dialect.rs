@@ -0,0 +1,23 @@
+/// Delimiters, quoting and header layout of CSV sources
+#[derive(Debug, Clone)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    pub comment: Option<u8>,
+    /// Column names of headerless files. When set, the first line is read as data.
+    pub column_names: Option<Vec<String>>,
+    /// Cell text read as null. Other cells, the empty ones too when it is not empty, are kept as text.
+    pub null: String,
+}
+
+impl CsvDialect {
+    pub fn make_parser(&self) -> csv_core::Reader {
+        csv_core::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .comment(self.comment)
+            .build()
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::Context;
+use csv_core::ReadRecordResult;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::entities::FilePosition;
+
+use super::{csv_dialect::CsvDialect, csv_row::csv_record_to_value, source_record::SourceRecord};
+
+/// Streaming RFC 4180 CSV reader over an async source. Quoted fields may span several lines.
+pub struct CsvReader<R> {
+    reader: R,
+    parser: csv_core::Reader,
+    headers: Vec<String>,
+    /// Cell text read as null
+    null: String,
+    /// Lines starting with it are skipped by the parser
+    comment: Option<u8>,
+    output: Vec<u8>,
+    ends: Vec<usize>,
+    /// Source text of the last record, kept for the records that cannot be decoded
+    raw_record: Vec<u8>,
+    record_number: u64,
+    line_number: u64,
+    /// Bytes read from the start of the file
+    byte_offset: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> CsvReader<R> {
+    pub async fn new(reader: R, csv_dialect: &CsvDialect) -> anyhow::Result<Self> {
+        let mut csv_reader = CsvReader {
+            reader,
+            parser: csv_dialect.make_parser(),
+            headers: Vec::new(),
+            null: csv_dialect.null.clone(),
+            comment: csv_dialect.comment,
+            output: vec![0; 4096],
+            ends: vec![0; 64],
+            raw_record: Vec::new(),
+            record_number: 0,
+            line_number: 0,
+            byte_offset: 0,
+        };
+
+        csv_reader.headers = match &csv_dialect.column_names {
+            Some(column_names) => column_names.clone(),
+            None => csv_reader.read_fields().await
+                .and_then(|fields| fields.transpose())
+                .context("CSV header")?
+                .context("CSV file has no header")?,
+        };
+
+        Ok(csv_reader)
+    }
+
+    /// Returns the next record as a row keyed by the header names, or `None` at the end of the file.
+    /// Records with invalid UTF-8 or the wrong number of fields are returned as unreadable.
+    pub async fn next_record(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        let previous_line_number = self.line_number;
+        self.record_number += 1;
+
+        let Some(fields) = self.read_fields().await? else {
+            return Ok(None);
+        };
+
+        let (skipped_lines, skipped_length) = skipped_lines(&self.raw_record, self.comment);
+        let first_line = previous_line_number + skipped_lines + 1;
+
+        let mut record = match fields.and_then(|fields| csv_record_to_value(&self.headers, &fields, &self.null)) {
+            Ok(value) => SourceRecord::new(first_line, value),
+            Err(error) => {
+                let error = error.context(format!("CSV record {} (line {first_line})", self.record_number));
+                let raw_record = String::from_utf8_lossy(&self.raw_record[skipped_length..]);
+                SourceRecord::unreadable(first_line, raw_record.trim_end_matches(['\r', '\n']).to_owned(), error)
+            },
+        };
+        record.position = Some(FilePosition { byte_offset: self.byte_offset, line_number: self.line_number });
+        record.byte_length = (self.raw_record.len() - skipped_length) as u64;
+
+        Ok(Some(record))
+    }
+
+    /// Continues with `reader`, which starts at `position`, the end of the first `records` records. The header
+    /// was read from the start of the file.
+    pub fn resume_at(&mut self, reader: R, position: FilePosition, records: u64) {
+        self.reader = reader;
+        self.byte_offset = position.byte_offset;
+        self.line_number = position.line_number;
+        self.record_number = records;
+    }
+
+    async fn read_fields(&mut self) -> anyhow::Result<Option<anyhow::Result<Vec<String>>>> {
+        let mut output_length = 0;
+        let mut ends_length = 0;
+        self.raw_record.clear();
+
+        loop {
+            let input = self.reader.fill_buf().await?;
+            let (result, read, written, ends_written) =
+                self.parser.read_record(input, &mut self.output[output_length..], &mut self.ends[ends_length..]);
+
+            self.line_number += input[..read].iter().filter(|byte| **byte == b'\n').count() as u64;
+            self.raw_record.extend_from_slice(&input[..read]);
+            self.reader.consume(read);
+            self.byte_offset += read as u64;
+            output_length += written;
+            ends_length += ends_written;
+
+            match result {
+                ReadRecordResult::InputEmpty => {},
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => return Ok(Some(self.decode_fields(ends_length))),
+                ReadRecordResult::End => return Ok(None),
+            }
+        }
+    }
+
+    fn decode_fields(&self, ends_length: usize) -> anyhow::Result<Vec<String>> {
+        let mut start = 0;
+
+        self.ends[..ends_length].iter().enumerate().map(|(field_index, end)| {
+            let field = std::str::from_utf8(&self.output[start..*end])
+                .with_context(|| format!("Field {} is not valid UTF-8", field_index + 1))?;
+            start = *end;
+            Ok(field.to_owned())
+        }).collect()
+    }
+}
+
+/// Counts the blank and comment lines the parser skipped before a record, and their length
+fn skipped_lines(raw_record: &[u8], comment: Option<u8>) -> (u64, usize) {
+    let mut lines = 0;
+    let mut length = 0;
+
+    loop {
+        match raw_record.get(length) {
+            Some(b'\r') => length += 1,
+            Some(b'\n') => {
+                lines += 1;
+                length += 1;
+            },
+            Some(byte) if Some(*byte) == comment => match raw_record[length..].iter().position(|byte| *byte == b'\n') {
+                Some(line_length) => {
+                    lines += 1;
+                    length += line_length + 1;
+                },
+                None => return (lines, raw_record.len()),
+            },
+            _ => return (lines, length),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    fn csv_dialect() -> CsvDialect {
+        CsvDialect { delimiter: b',', quote: b'"', escape: None, comment: None, column_names: None, null: String::new() }
+    }
+
+    /// Reads every record of `text` with each of several buffer sizes, so fields are split across buffers,
+    /// checking they all read the same. Unreadable records read as their text.
+    async fn read_records(text: &str, csv_dialect: &CsvDialect) -> anyhow::Result<Vec<(u64, Result<Value, String>)>> {
+        let mut all_records = Vec::new();
+
+        for capacity in [1, 2, 3, 7, 64, 8192] {
+            let mut csv_reader = CsvReader::new(BufReader::with_capacity(capacity, text.as_bytes()), csv_dialect).await?;
+
+            let mut records = Vec::new();
+            while let Some(record) = csv_reader.next_record().await? {
+                records.push((record.line_number, record.value.map_err(|unreadable| unreadable.text)));
+            }
+            all_records.push(records);
+        }
+
+        assert!(all_records.windows(2).all(|pair| pair[0] == pair[1]), "Buffer sizes read differently: {all_records:?}");
+        Ok(all_records.remove(0))
+    }
+
+    fn values(records: Vec<(u64, Result<Value, String>)>) -> Vec<Value> {
+        records.into_iter().map(|(_, value)| value.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_quoted_fields_spanning_lines() {
+        let records = read_records("id,name\n1,\"a, \"\"b\"\"\"\n2,\"c\r\nd\"\n3,\n", &csv_dialect()).await.unwrap();
+
+        let line_numbers = records.iter().map(|(line_number, _)| *line_number).collect::<Vec<_>>();
+        assert_eq!(line_numbers, [2, 3, 5]);
+        assert_eq!(values(records), [
+            json!({"id": "1", "name": "a, \"b\""}),
+            json!({"id": "2", "name": "c\r\nd"}),
+            json!({"id": "3", "name": null}),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn reads_other_delimiters_quotes_and_escapes() {
+        let csv_dialect = CsvDialect { delimiter: b'\t', quote: b'\'', escape: Some(b'\\'), ..csv_dialect() };
+        let records = read_records("id\tname\n1\t'it\\'s\ta tab'\n", &csv_dialect).await.unwrap();
+
+        assert_eq!(values(records), [json!({"id": "1", "name": "it's\ta tab"})]);
+    }
+
+    #[tokio::test]
+    async fn numbers_records_after_comment_and_blank_lines() {
+        let csv_dialect = CsvDialect { comment: Some(b'#'), ..csv_dialect() };
+        let records = read_records("# export\nid,name\n\n# first\n1,a\r\n\r\n# second, third\n2,b\n3\n", &csv_dialect).await.unwrap();
+
+        let line_numbers = records.iter().map(|(line_number, _)| *line_number).collect::<Vec<_>>();
+        assert_eq!(line_numbers, [5, 8, 9]);
+        assert_eq!(records[2].1, Err("3".to_owned()));
+        assert_eq!(values(records[..2].to_vec()), [json!({"id": "1", "name": "a"}), json!({"id": "2", "name": "b"})]);
+    }
+
+    #[tokio::test]
+    async fn reads_headerless_files_with_the_given_columns() {
+        let csv_dialect = CsvDialect { column_names: Some(vec!["id".to_owned(), "name".to_owned()]), ..csv_dialect() };
+        let records = read_records("1,a\n2,b", &csv_dialect).await.unwrap();
+
+        assert_eq!(records[1].0, 2);
+        assert_eq!(values(records), [json!({"id": "1", "name": "a"}), json!({"id": "2", "name": "b"})]);
+    }
+
+    #[tokio::test]
+    async fn measures_the_source_text_of_each_record() {
+        let csv_dialect = CsvDialect { comment: Some(b'#'), ..csv_dialect() };
+        let mut csv_reader = CsvReader::new("id,name\n1,\"a\nb\"\n# skipped\n\n2,c\n".as_bytes(), &csv_dialect).await.unwrap();
+
+        assert_eq!(csv_reader.next_record().await.unwrap().unwrap().byte_length, "1,\"a\nb\"\n".len() as u64);
+        assert_eq!(csv_reader.next_record().await.unwrap().unwrap().byte_length, "2,c\n".len() as u64);
+    }
+
+    #[tokio::test]
+    async fn returns_records_with_the_wrong_number_of_fields_as_unreadable() {
+        let mut csv_reader = CsvReader::new("id,name\n1,a,extra\n2,b\n".as_bytes(), &csv_dialect()).await.unwrap();
+
+        let record = csv_reader.next_record().await.unwrap().unwrap();
+        let unreadable = record.value.unwrap_err();
+        assert_eq!(unreadable.text, "1,a,extra");
+        assert_eq!(format!("{:#}", unreadable.error), "CSV record 1 (line 2): CSV record has 3 fields, but the header has 2");
+
+        let record = csv_reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.value.unwrap(), json!({"id": "2", "name": "b"}));
+        assert!(csv_reader.next_record().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,40 @@
+use serde_json::{Map, Value};
+
+/// Builds a row from a CSV record. Cells are kept as text, to be converted to the target column types
+/// when bound, and the cells that are `null` are read as null.
+pub fn csv_record_to_value(headers: &[String], record: &[String], null: &str) -> anyhow::Result<Value> {
+    if headers.len() != record.len() {
+        anyhow::bail!("CSV record has {} fields, but the header has {}", record.len(), headers.len())
+    }
+
+    let fields = headers.iter().zip(record.iter()).map(|(name, cell)| {
+        let value = if cell == null { Value::Null } else { Value::String(cell.to_owned()) };
+        (name.clone(), value)
+    }).collect::<Map<_, _>>();
+
+    Ok(Value::Object(fields))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_empty_cells_as_null_by_default() {
+        let row = csv_record_to_value(&strings(&["id", "name"]), &strings(&["1", ""]), "").unwrap();
+        assert_eq!(row, json!({"id": "1", "name": null}));
+    }
+
+    #[test]
+    fn keeps_empty_cells_as_text_with_a_null_marker() {
+        let row = csv_record_to_value(&strings(&["id", "name", "email"]), &strings(&["1", "", "NULL"]), "NULL").unwrap();
+        assert_eq!(row, json!({"id": "1", "name": "", "email": null}));
+    }
+}
@@ -1,12 +1,14 @@
 use async_trait::async_trait;
 
+use super::source_record::SourceRecord;
+
 #[async_trait]
 pub trait DatasetExt {
     type DatasetType: DatasetExt;
 
-    async fn next_line(&self) -> anyhow::Result<Option<serde_json::Value>>;
+    async fn next_line(&self) -> anyhow::Result<Option<SourceRecord>>;
 
-    async fn next_batch(&self, batch_size: u32) -> anyhow::Result<Option<Vec<serde_json::Value>>> {
+    async fn next_batch(&self, batch_size: u32) -> anyhow::Result<Option<Vec<SourceRecord>>> {
         let mut batch = Vec::new();
 
         for _ in 0..batch_size {
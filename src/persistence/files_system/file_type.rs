@@ -1,5 +1,8 @@
+#[allow(clippy::upper_case_acronyms)]
 #[derive(clap::ValueEnum, Debug, Clone)]
 pub enum FileType {
     JSON,
-    CSV
-}
\ No newline at end of file
+    CSV,
+    Parquet,
+    Avro,
+}
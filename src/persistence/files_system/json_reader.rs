@@ -0,0 +1,447 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::entities::FilePosition;
+
+use super::source_record::SourceRecord;
+
+/// A JSON pointer (RFC 6901) to the records inside each document of a JSON file
+#[derive(Debug, Clone, Default)]
+pub struct JsonPointer(Vec<String>);
+
+impl FromStr for JsonPointer {
+    type Err = String;
+
+    fn from_str(pointer: &str) -> Result<Self, Self::Err> {
+        if pointer.is_empty() {
+            return Ok(JsonPointer::default());
+        }
+
+        let segments = pointer.strip_prefix('/')
+            .ok_or_else(|| format!("a JSON pointer must start with `/`, found {pointer:?}"))?;
+
+        let segments = segments.split('/')
+            .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+            .collect();
+
+        Ok(JsonPointer(segments))
+    }
+}
+
+
+/// Streaming JSON reader over an async source. Handles newline delimited JSON, concatenated and pretty
+/// printed documents, and top-level arrays, whose elements are the records. With a pointer, the records
+/// are the value it points to in each document, or the elements of that value when it is an array.
+pub struct JsonReader<R> {
+    reader: R,
+    scanner: JsonScanner,
+    /// Bytes read from the start of the file
+    byte_offset: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> JsonReader<R> {
+    pub fn new(reader: R, pointer: JsonPointer) -> Self {
+        let scanner = JsonScanner {
+            pointer,
+            stack: Vec::new(),
+            in_string: false,
+            escaped: false,
+            in_scalar: false,
+            key: None,
+            record: Vec::new(),
+            record_depth: None,
+            record_number: 0,
+            record_line: 0,
+            line_number: 1,
+        };
+
+        JsonReader { reader, scanner, byte_offset: 0 }
+    }
+
+    /// Continues with `reader`, which starts at `position`, the end of the first `records` records
+    pub fn resume_at(&mut self, reader: R, position: FilePosition, records: u64) {
+        self.reader = reader;
+        self.byte_offset = position.byte_offset;
+        self.scanner.line_number = position.line_number;
+        self.scanner.record_number = records;
+    }
+
+    /// Returns the next record, or `None` at the end of the file. A record that is not valid JSON is returned
+    /// as unreadable, but a broken document structure fails the file.
+    pub async fn next_record(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        loop {
+            let input = self.reader.fill_buf().await?;
+            if input.is_empty() {
+                let record = self.scanner.finish()?;
+                return Ok(record.map(|record| self.with_position(record)));
+            }
+
+            let mut consumed = input.len();
+            let mut record_ended = false;
+
+            for (position, byte) in input.iter().enumerate() {
+                match self.scanner.scan(*byte)? {
+                    Scan::Byte => {},
+                    Scan::RecordEnd => {
+                        (consumed, record_ended) = (position + 1, true);
+                        break;
+                    },
+                    Scan::RecordEndBefore => {
+                        (consumed, record_ended) = (position, true);
+                        break;
+                    },
+                }
+            }
+
+            self.reader.consume(consumed);
+            self.byte_offset += consumed as u64;
+
+            if record_ended {
+                let record = self.scanner.take_record();
+                return Ok(Some(self.with_position(record)));
+            }
+        }
+    }
+
+    /// Sets where a record ends when it is a whole document, since reading can only resume between documents
+    fn with_position(&self, mut record: SourceRecord) -> SourceRecord {
+        if self.scanner.stack.is_empty() {
+            record.position = Some(FilePosition { byte_offset: self.byte_offset, line_number: self.scanner.line_number });
+        }
+        record
+    }
+}
+
+
+enum Scan {
+    Byte,
+    /// The byte closed the current record
+    RecordEnd,
+    /// The byte ended the current scalar record without being part of it, and must be scanned again
+    RecordEndBefore,
+}
+
+struct Frame {
+    is_array: bool,
+    /// The elements of this array are records
+    holds_records: bool,
+    /// Index of the current element of an array
+    index: usize,
+    /// Key of the current member of an object
+    key: Option<String>,
+    awaiting_key: bool,
+}
+
+/// Tracks the structure of the JSON text byte by byte, just enough to find where the records start and end
+/// and the path of every value outside of them. The records themselves are parsed by serde.
+struct JsonScanner {
+    pointer: JsonPointer,
+    stack: Vec<Frame>,
+    in_string: bool,
+    escaped: bool,
+    in_scalar: bool,
+    key: Option<Vec<u8>>,
+    record: Vec<u8>,
+    record_depth: Option<usize>,
+    record_number: u64,
+    record_line: u64,
+    line_number: u64,
+}
+
+impl JsonScanner {
+    fn scan(&mut self, byte: u8) -> anyhow::Result<Scan> {
+        if self.in_string {
+            return self.scan_string(byte);
+        }
+
+        if self.in_scalar && (byte.is_ascii_whitespace() || matches!(byte, b',' | b']' | b'}')) {
+            self.in_scalar = false;
+            if self.end_value() {
+                return Ok(Scan::RecordEndBefore);
+            }
+        }
+
+        if byte == b'\n' {
+            self.line_number += 1;
+        }
+
+        match byte {
+            b'"' => {
+                if self.stack.last().is_some_and(|frame| frame.awaiting_key) {
+                    if self.record_depth.is_none() {
+                        self.key = Some(vec![byte]);
+                    }
+                } else {
+                    self.begin_value(byte);
+                }
+                self.in_string = true;
+            },
+            b'{' | b'[' => {
+                let holds_records = self.begin_value(byte);
+                self.stack.push(Frame { is_array: byte == b'[', holds_records, index: 0, key: None, awaiting_key: byte == b'{' });
+            },
+            b'}' | b']' => {
+                let frame = self.stack.pop()
+                    .with_context(|| format!("Unexpected `{}` at line {}", byte as char, self.line_number))?;
+
+                if frame.is_array != (byte == b']') {
+                    anyhow::bail!("Mismatched `{}` at line {}", byte as char, self.line_number);
+                }
+
+                self.capture(byte);
+                return Ok(if self.end_value() { Scan::RecordEnd } else { Scan::Byte });
+            },
+            b':' => {
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.awaiting_key = false;
+                }
+            },
+            b',' => {
+                if let Some(frame) = self.stack.last_mut() {
+                    if frame.is_array {
+                        frame.index += 1;
+                    } else {
+                        frame.awaiting_key = true;
+                    }
+                }
+            },
+            byte if byte.is_ascii_whitespace() => {},
+            _ => {
+                if !self.in_scalar {
+                    self.begin_value(byte);
+                    self.in_scalar = true;
+                }
+            },
+        }
+
+        self.capture(byte);
+        Ok(Scan::Byte)
+    }
+
+    fn scan_string(&mut self, byte: u8) -> anyhow::Result<Scan> {
+        self.capture(byte);
+        if let Some(key) = &mut self.key {
+            key.push(byte);
+        }
+
+        if self.escaped {
+            self.escaped = false;
+        } else if byte == b'\\' {
+            self.escaped = true;
+        } else if byte == b'"' {
+            self.in_string = false;
+
+            if let Some(key) = self.key.take() {
+                let key = serde_json::from_slice(&key).with_context(|| format!("Invalid key at line {}", self.line_number))?;
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.key = Some(key);
+                }
+            } else if self.end_value() {
+                return Ok(Scan::RecordEnd);
+            }
+        } else if byte == b'\n' {
+            self.line_number += 1;
+        }
+
+        Ok(Scan::Byte)
+    }
+
+    /// Starts capturing the value beginning with `byte` when it is a record. Returns whether it is the
+    /// array pointed to, whose elements are the records.
+    fn begin_value(&mut self, byte: u8) -> bool {
+        if self.record_depth.is_some() {
+            return false;
+        }
+
+        let in_records_array = self.stack.last().is_some_and(|frame| frame.holds_records);
+        let is_pointed_to = !in_records_array && self.is_at_pointer();
+
+        if in_records_array || (is_pointed_to && byte != b'[') {
+            self.record.clear();
+            self.record_depth = Some(self.stack.len());
+            self.record_line = self.line_number;
+            false
+        } else {
+            is_pointed_to
+        }
+    }
+
+    /// Whether the value ending now is the current record
+    fn end_value(&self) -> bool {
+        self.record_depth == Some(self.stack.len())
+    }
+
+    fn is_at_pointer(&self) -> bool {
+        let JsonPointer(segments) = &self.pointer;
+
+        self.stack.len() == segments.len() && self.stack.iter().zip(segments).all(|(frame, segment)| {
+            if frame.is_array {
+                frame.index.to_string() == *segment
+            } else {
+                frame.key.as_deref() == Some(segment.as_str())
+            }
+        })
+    }
+
+    fn capture(&mut self, byte: u8) {
+        if self.record_depth.is_some() {
+            self.record.push(byte);
+        }
+    }
+
+    fn take_record(&mut self) -> SourceRecord {
+        self.record_depth = None;
+        self.record_number += 1;
+
+        let mut record = match serde_json::from_slice(&self.record) {
+            Ok(value) => SourceRecord::new(self.record_line, value),
+            Err(error) => {
+                let error = anyhow::Error::from(error).context(format!("JSON record {} (line {})", self.record_number, self.record_line));
+                SourceRecord::unreadable(self.record_line, String::from_utf8_lossy(&self.record).into_owned(), error)
+            },
+        };
+        record.byte_length = self.record.len() as u64;
+
+        record
+    }
+
+    fn finish(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        if self.in_scalar {
+            self.in_scalar = false;
+            if self.end_value() {
+                return Ok(Some(self.take_record()));
+            }
+        }
+
+        if self.in_string || !self.stack.is_empty() || self.record_depth.is_some() {
+            anyhow::bail!("Unexpected end of JSON input at line {}", self.line_number);
+        }
+
+        if self.record_number == 0 && !self.pointer.0.is_empty() {
+            log::warn!("No records found at JSON pointer /{pointer}", pointer=self.pointer.0.join("/"));
+        }
+
+        Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    /// Reads every record of `text` with each of several buffer sizes, so values are split across buffers,
+    /// checking they all read the same. Unreadable records read as their text.
+    async fn read_records(text: &str, pointer: &str) -> anyhow::Result<Vec<(u64, Result<Value, String>)>> {
+        let mut all_records = Vec::new();
+
+        for capacity in [1, 2, 3, 7, 64, 8192] {
+            let reader = BufReader::with_capacity(capacity, text.as_bytes());
+            let mut json_reader = JsonReader::new(reader, pointer.parse().map_err(anyhow::Error::msg)?);
+
+            let mut records = Vec::new();
+            while let Some(record) = json_reader.next_record().await? {
+                records.push((record.line_number, record.value.map_err(|unreadable| unreadable.text)));
+            }
+            all_records.push(records);
+        }
+
+        assert!(all_records.windows(2).all(|pair| pair[0] == pair[1]), "Buffer sizes read differently: {all_records:?}");
+        Ok(all_records.remove(0))
+    }
+
+    fn values(records: Vec<(u64, Result<Value, String>)>) -> Vec<Value> {
+        records.into_iter().map(|(_, value)| value.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_newline_delimited_json() {
+        let records = read_records("{\"a\": 1}\n{\"a\": 2}\n\n{\"a\": 3}", "").await.unwrap();
+
+        let line_numbers = records.iter().map(|(line_number, _)| *line_number).collect::<Vec<_>>();
+        assert_eq!(line_numbers, [1, 2, 4]);
+        assert_eq!(values(records), [json!({"a": 1}), json!({"a": 2}), json!({"a": 3})]);
+    }
+
+    #[tokio::test]
+    async fn reads_concatenated_pretty_printed_documents() {
+        let records = read_records("{\n  \"a\": [1, 2]\n}{\"b\": {}}\n", "").await.unwrap();
+
+        assert_eq!(records[1].0, 3);
+        assert_eq!(values(records), [json!({"a": [1, 2]}), json!({"b": {}})]);
+    }
+
+    #[tokio::test]
+    async fn reads_the_elements_of_top_level_arrays() {
+        let records = read_records("[{\"a\": 1},\n {\"a\": 2}]\n[3, \"x\", null, [4]]", "").await.unwrap();
+
+        assert_eq!(records[1].0, 2);
+        assert_eq!(values(records), [json!({"a": 1}), json!({"a": 2}), json!(3), json!("x"), json!(null), json!([4])]);
+    }
+
+    #[tokio::test]
+    async fn reads_scalar_records() {
+        let records = read_records("1\n2.5 true\n\"x\"", "").await.unwrap();
+        assert_eq!(values(records), [json!(1), json!(2.5), json!(true), json!("x")]);
+    }
+
+    #[tokio::test]
+    async fn reads_the_values_at_a_pointer() {
+        let text = r#"{"meta": {"items": [9]}, "data": {"items": [{"a": 1}, {"a": 2}]}}
+                      {"data": {"items": [{"a": 3}], "other": [{"a": 0}]}}"#;
+
+        let records = read_records(text, "/data/items").await.unwrap();
+        assert_eq!(values(records), [json!({"a": 1}), json!({"a": 2}), json!({"a": 3})]);
+
+        let records = read_records(text, "/data/items/1").await.unwrap();
+        assert_eq!(values(records), [json!({"a": 2})]);
+
+        let records = read_records(text, "/data").await.unwrap();
+        assert_eq!(records.len(), 2);
+
+        let records = read_records(r#"{"a/b": {"c~d": [1]}}"#, "/a~1b/c~0d").await.unwrap();
+        assert_eq!(values(records), [json!(1)]);
+
+        assert!(read_records(text, "/missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_brackets_and_escaped_quotes_in_strings() {
+        let text = r#"{"s": "a]}\"[{,", "t\"]": "\\"}
+                      ["]", "\\\""]"#;
+
+        let records = read_records(text, "").await.unwrap();
+        assert_eq!(values(records), [json!({"s": "a]}\"[{,", "t\"]": "\\"}), json!("]"), json!("\\\"")]);
+
+        let records = read_records(r#"{"k]\"": {"x": 1}, "data": [{"s": "}]"}]}"#, "/data").await.unwrap();
+        assert_eq!(values(records), [json!({"s": "}]"})]);
+    }
+
+    #[tokio::test]
+    async fn returns_invalid_records_as_unreadable() {
+        let records = read_records("{\"a\": tru}\n{\"a\": 1}", "").await.unwrap();
+
+        assert_eq!(records[0], (1, Err("{\"a\": tru}".to_owned())));
+        assert_eq!(records[1], (2, Ok(json!({"a": 1}))));
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_unterminated_final_value() {
+        for text in ["{\"a\": 1}\n{\"a\": ", "[1, 2", "\"abc", "{\"a\": \"b}"] {
+            let error = read_records(text, "").await.unwrap_err();
+            assert!(error.to_string().starts_with("Unexpected end of JSON input"), "{text:?}: {error}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_mismatched_brackets() {
+        for text in ["{\"a\": 1]", "]"] {
+            assert!(read_records(text, "").await.is_err(), "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use chrono::NaiveDateTime;
+use serde_json::{Number, Value};
+
+/// JSON has no representation for NaN and infinities, which are kept in their textual form
+pub fn float_to_json(value: f64) -> Value {
+    Number::from_f64(value).map(Value::Number).unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Binary values are written in hexadecimal, which blob and uuid columns both parse
+pub fn bytes_to_json(bytes: &[u8]) -> Value {
+    Value::String(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Instants are written in ISO 8601 form, in UTC
+pub fn date_time_to_json(date_time: NaiveDateTime) -> Value {
+    Value::String(date_time.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string())
+}
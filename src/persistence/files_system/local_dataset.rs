@@ -1,90 +1,170 @@
-use std::{path::Path};
+use std::{io::SeekFrom, path::{Path, PathBuf}};
 
-use tokio::{io::{AsyncBufReadExt, BufReader, Lines}, fs::File, sync::RwLock};
+use tokio::{io::{AsyncSeekExt, BufReader}, fs::File, sync::Mutex};
 use async_trait::async_trait;
 
-use super::{file_type::FileType, dataset_ext::DatasetExt};
+use crate::entities::SourceProgress;
+
+use super::{file_type::FileType, compression::{SourceReader, decompress}, dataset_ext::DatasetExt, records_reader::RecordsReader,
+            source_options::SourceOptions, source_record::SourceRecord, is_glob_pattern, is_hidden_file};
 
 pub struct LocalDataset {
-    lines: RwLock<Lines<BufReader<File>>>,
-    file_type: FileType,
-    csv_header: Option<String>,
+    records: Mutex<RecordsReader<SourceReader>>,
 }
 
 
 impl LocalDataset {
-    pub async fn new(source_path: &str, file_type: &FileType) -> anyhow::Result<Self> {
-        match file_type {
-            FileType::JSON => LocalDataset::load_json(source_path).await,
-            FileType::CSV => LocalDataset::load_csv(source_path).await,
-        }
-    }
+    /// Opens the file after the records acknowledged in `progress`
+    pub async fn new(source_path: &str, source_options: &SourceOptions, progress: &SourceProgress) -> anyhow::Result<Self> {
+        let file = open_local_file(source_path).await?;
+        let records = match source_options.file_type {
+            FileType::Parquet => RecordsReader::new_parquet(Box::new(file), progress.records).await?,
+            _ => {
+                let file_size = file.metadata().await?.len();
+                let (reader, compression) = decompress(BufReader::new(file), source_path, source_options.compression).await?;
+                let mut records = RecordsReader::new(reader, source_options).await?;
+
+                match records.resume_position(progress, compression, file_size)? {
+                    Some(position) => {
+                        let mut file = File::open(source_path).await?;
+                        file.seek(SeekFrom::Start(position.byte_offset)).await?;
+                        records.resume_at(Box::new(BufReader::new(file)), position, progress.records)?;
+                    },
+                    None => records.skip_records(progress.records).await?,
+                }
 
-    async fn load_json(source_path: &str) -> anyhow::Result<Self> {
-        let lines = open_local_file(source_path).await?;
-        let lines_lock = RwLock::new(lines);
-        
-        let dataset = LocalDataset {
-            lines: lines_lock, file_type: FileType::JSON,
-            csv_header: None
+                records
+            },
         };
 
-        Ok(dataset)
-    }
-
-    async fn load_csv(source_path: &str) -> anyhow::Result<Self> {
-        let mut lines = open_local_file(source_path).await?;
-        let csv_header = lines.next_line().await?;
-
-        let lines_lock = RwLock::new(lines);
         let dataset = LocalDataset {
-            lines: lines_lock,
-            file_type: FileType::CSV,
-            csv_header
+            records: Mutex::new(records),
         };
 
         Ok(dataset)
-    }    
+    }
 }
 
 
 #[async_trait]
 impl DatasetExt for LocalDataset {
-    
+
     type DatasetType = Self;
 
-    async fn next_line(&self) -> anyhow::Result<Option<serde_json::Value>> {
-        let mut unlocked_lines = self.lines.write().await;
-        
-        if let Some(current_line) = unlocked_lines.next_line().await? {
-            match self.file_type {
-                FileType::JSON => {        
-                    let value = serde_json::from_str(&current_line)?;
-                    Ok(value)
-                },
-                FileType::CSV => {
-                    let csv_line_with_header = self.csv_header.clone().unwrap() + "\n" + &current_line;
-                    let mut csv_reader = csv::Reader::from_reader(csv_line_with_header.as_bytes());
-                    let mut csv_iter = csv_reader.deserialize();
-
-                    let value: serde_json::Value = csv_iter.next().unwrap()?;
-                    Ok(Some(value))
-                }
-            }
+    async fn next_line(&self) -> anyhow::Result<Option<SourceRecord>> {
+        let mut unlocked_records = self.records.lock().await;
+        unlocked_records.next_record().await
+    }
+
+}
+
+
+/// Lists the files of a directory, recursively and skipping hidden entries, or the files matching a glob
+/// pattern. Returns `None` when `source_path` names a single file.
+pub fn list_local_files(source_path: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let path = Path::new(source_path);
+
+    let mut file_paths = if path.is_dir() {
+        let mut file_paths = Vec::new();
+        walk_directory(path, &mut file_paths)?;
+        file_paths
+    } else if is_glob_pattern(source_path) {
+        glob::glob(source_path)?
+            .filter_map(|entry| entry.map_err(|error| log::warn!("Skipping {error}")).ok())
+            .filter(|file_path| file_path.is_file())
+            .collect()
+    } else if path.exists() {
+        return Ok(None);
+    } else {
+        anyhow::bail!("{source_path} does not exist");
+    };
+
+    file_paths.sort();
+
+    let file_paths = file_paths.into_iter()
+        .map(|file_path| file_path.to_string_lossy().into_owned())
+        .collect();
+
+    Ok(Some(file_paths))
+}
+
+fn walk_directory(directory: &Path, file_paths: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry_path = entry?.path();
+        if entry_path.file_name().and_then(|name| name.to_str()).is_some_and(is_hidden_file) {
+            continue;
+        }
+
+        if entry_path.is_dir() {
+            walk_directory(&entry_path, file_paths)?;
         } else {
-            Ok(None)
+            file_paths.push(entry_path);
         }
     }
 
+    Ok(())
 }
 
-
-async fn open_local_file(source_path: &str) -> anyhow::Result<Lines<BufReader<File>>> {
+async fn open_local_file(source_path: &str) -> anyhow::Result<File> {
     let path = Path::new(source_path);
     let file = File::open(path).await?;
-    let reader = BufReader::new(file).lines();
-    
+
     log::info!("Opening file {filename}", filename=source_path);
 
-    Ok(reader)
-}
\ No newline at end of file
+    Ok(file)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Creates the files, and their directories, under a new temporary directory
+    fn directory_with(file_paths: &[&str]) -> tempfile::TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        for file_path in file_paths {
+            let file_path = directory.path().join(file_path);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, "{}").unwrap();
+        }
+        directory
+    }
+
+    fn relative_paths(directory: &tempfile::TempDir, file_paths: Option<Vec<String>>) -> Vec<String> {
+        let prefix = format!("{}/", directory.path().display());
+        file_paths.unwrap().iter().map(|file_path| file_path.strip_prefix(&prefix).unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn lists_directories_recursively_in_order_without_hidden_entries() {
+        let directory = directory_with(&["b.json", "a/2.json", "a/10.json", "_SUCCESS", ".a.json.crc", "_temporary/c.json", ".git/d.json", "c/e_f.json"]);
+
+        let file_paths = list_local_files(directory.path().to_str().unwrap()).unwrap();
+        assert_eq!(relative_paths(&directory, file_paths), ["a/10.json", "a/2.json", "b.json", "c/e_f.json"]);
+    }
+
+    #[test]
+    fn lists_the_files_matching_glob_patterns_in_order() {
+        let directory = directory_with(&["2024/02/b.csv", "2024/01/a.csv", "2024/01/a.json", "2023/12/z.csv"]);
+        let root = directory.path().display();
+
+        let file_paths = list_local_files(&format!("{root}/2024/*/*.csv")).unwrap();
+        assert_eq!(relative_paths(&directory, file_paths), ["2024/01/a.csv", "2024/02/b.csv"]);
+
+        let file_paths = list_local_files(&format!("{root}/**/a.*")).unwrap();
+        assert_eq!(relative_paths(&directory, file_paths), ["2024/01/a.csv", "2024/01/a.json"]);
+
+        assert_eq!(list_local_files(&format!("{root}/*/*")).unwrap(), Some(vec![]));
+        assert_eq!(list_local_files(&format!("{root}/*.parquet")).unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn does_not_list_single_files_and_fails_missing_paths() {
+        let directory = directory_with(&["a.json"]);
+
+        assert_eq!(list_local_files(directory.path().join("a.json").to_str().unwrap()).unwrap(), None);
+        assert!(list_local_files(directory.path().join("b.json").to_str().unwrap()).is_err());
+    }
+}
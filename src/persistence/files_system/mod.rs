@@ -1,34 +1,96 @@
 use self::{local_dataset::LocalDataset, s3_dataset::S3Dataset};
 
+mod avro_reader;
+mod checkpoint_store;
+mod compression;
+mod csv_dialect;
+mod csv_reader;
+mod csv_row;
 mod dataset_ext;
 mod file_type;
+mod json_reader;
+mod json_value;
 mod local_dataset;
+mod parquet_reader;
+mod records_reader;
+mod reject_writer;
 mod s3_dataset;
+mod s3_object_reader;
+mod source_options;
+mod source_record;
 
+use std::path::Path;
+
+use anyhow::Context;
 use async_trait::async_trait;
+use glob::Pattern;
+
+use crate::entities::SourceProgress;
+pub use checkpoint_store::CheckpointStore;
 pub use file_type::FileType;
+pub use csv_dialect::CsvDialect;
+pub use compression::Compression;
+pub use json_reader::JsonPointer;
+pub use s3_dataset::{S3Options, make_s3_client};
 pub use dataset_ext::DatasetExt;
+pub use reject_writer::RejectWriter;
+pub use source_options::SourceOptions;
+pub use source_record::{SourceRecord, UnreadableRecord};
 
 
 pub enum Dataset {
     S3(S3Dataset),
-    Local(LocalDataset),
+    Local(Box<LocalDataset>),
 }
 
 impl Dataset {
-    pub async fn load(source_path: &str, file_type: &FileType, access_key: Option<String>,
-        secret_key: Option<String>, region: Option<String>, endpoint: Option<String>) -> anyhow::Result<Dataset> {
-        let dataset = if source_path.starts_with("s3://") || source_path.starts_with("s3a://") {
-            let dataset = S3Dataset::new(source_path, file_type, access_key,
-                secret_key, region, endpoint).await?;
+    pub async fn load(source_path: &str, source_options: &SourceOptions) -> anyhow::Result<Dataset> {
+        Dataset::resume(source_path, source_options, &SourceProgress::default()).await
+    }
+
+    /// Opens a source after the records acknowledged in `progress`. Uncompressed CSV and JSON files continue at
+    /// the byte offset of the last of them and Parquet files at its row group, while the others read past them.
+    pub async fn resume(source_path: &str, source_options: &SourceOptions, progress: &SourceProgress) -> anyhow::Result<Dataset> {
+        let dataset = if is_s3_path(source_path) {
+            let dataset = S3Dataset::new(source_path, source_options, progress).await?;
             Dataset::S3(dataset)
         } else {
-            let dataset = LocalDataset::new(source_path, file_type).await?;
-            Dataset::Local(dataset)
+            let dataset = LocalDataset::new(source_path, source_options, progress).await?;
+            Dataset::Local(Box::new(dataset))
         };
 
         Ok(dataset)
     }
+
+    /// Expands a directory, glob pattern or S3 prefix into the sorted paths of its files, keeping those whose
+    /// file name matches any `include` pattern (when given) and no `exclude` pattern
+    pub async fn list_sources(source_path: &str, s3_client: Option<&aws_sdk_s3::Client>, include: &[Pattern], exclude: &[Pattern]) -> anyhow::Result<Vec<String>> {
+        let listed_paths = if is_s3_path(source_path) {
+            s3_dataset::list_s3_objects(source_path, s3_client_for(s3_client, source_path)?).await?
+        } else {
+            local_dataset::list_local_files(source_path)?
+        };
+
+        let Some(listed_paths) = listed_paths else {
+            return Ok(vec![source_path.to_owned()]);
+        };
+
+        let source_paths: Vec<String> = listed_paths.into_iter()
+            .filter(|listed_path| {
+                let file_name = Path::new(listed_path).file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                (include.is_empty() || include.iter().any(|pattern| pattern.matches(file_name)))
+                    && !exclude.iter().any(|pattern| pattern.matches(file_name))
+            })
+            .collect();
+
+        if source_paths.is_empty() {
+            anyhow::bail!("No source files found at {source_path}");
+        }
+
+        log::info!("Loading {count} source files from {source_path}", count=source_paths.len());
+
+        Ok(source_paths)
+    }
 }
 
 
@@ -36,10 +98,67 @@ impl Dataset {
 impl DatasetExt for Dataset {
     type DatasetType = Dataset;
 
-    async fn next_line(&self) -> anyhow::Result<Option<serde_json::Value>> {
+    async fn next_line(&self) -> anyhow::Result<Option<SourceRecord>> {
         match self {
             Dataset::S3(dataset) => dataset.next_line().await,
             Dataset::Local(dataset) => dataset.next_line().await
         }
     }
 }
+
+
+pub fn is_s3_path(source_path: &str) -> bool {
+    source_path.starts_with("s3://") || source_path.starts_with("s3a://")
+}
+
+/// The client of an `s3://` path, built once for the run whenever any of its paths is on S3
+fn s3_client_for<'a>(s3_client: Option<&'a aws_sdk_s3::Client>, path: &str) -> anyhow::Result<&'a aws_sdk_s3::Client> {
+    s3_client.with_context(|| format!("No S3 client to access {path}"))
+}
+
+fn is_glob_pattern(source_path: &str) -> bool {
+    source_path.contains(['*', '?', '['])
+}
+
+/// Files and directories starting with `.` or `_`, like Spark's `_SUCCESS` markers and `.crc` checksums
+fn is_hidden_file(file_name: &str) -> bool {
+    file_name.starts_with('.') || file_name.starts_with('_')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn filters_the_listed_files_by_name() {
+        let directory = tempfile::tempdir().unwrap();
+        for file_name in ["a.json", "b.json", "b.json.bak", "c.csv"] {
+            std::fs::write(directory.path().join(file_name), "{}").unwrap();
+        }
+        let root = directory.path().to_str().unwrap();
+        let file_names = |source_paths: Vec<String>| source_paths.iter()
+            .map(|source_path| source_path.strip_prefix(&format!("{root}/")).unwrap().to_owned())
+            .collect::<Vec<_>>();
+
+        let source_paths = Dataset::list_sources(root, None, &[Pattern::new("*.json").unwrap()], &[Pattern::new("a*").unwrap()]).await.unwrap();
+        assert_eq!(file_names(source_paths), ["b.json"]);
+
+        let source_paths = Dataset::list_sources(root, None, &[], &[]).await.unwrap();
+        assert_eq!(file_names(source_paths), ["a.json", "b.json", "b.json.bak", "c.csv"]);
+
+        let single_file = directory.path().join("c.csv").to_string_lossy().into_owned();
+        assert_eq!(Dataset::list_sources(&single_file, None, &[], &[]).await.unwrap(), [single_file]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_file_matches() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("a.json"), "{}").unwrap();
+        let root = directory.path().to_str().unwrap();
+
+        let error = Dataset::list_sources(root, None, &[Pattern::new("*.csv").unwrap()], &[]).await.unwrap_err();
+        assert_eq!(error.to_string(), format!("No source files found at {root}"));
+        assert!(Dataset::list_sources(&format!("{root}/*.parquet"), None, &[], &[]).await.is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use arrow_array::{Array, RecordBatch, cast::AsArray, types::*};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::{DataType, TimeUnit};
+use futures::StreamExt;
+use parquet::arrow::{ParquetRecordBatchStreamBuilder, async_reader::{AsyncFileReader, ParquetRecordBatchStream}};
+use serde_json::{Map, Value};
+
+use super::{json_value::{bytes_to_json, date_time_to_json, float_to_json}, source_record::SourceRecord};
+
+/// Streams the rows of a Parquet file, decoding one row group at a time
+pub struct ParquetReader {
+    stream: ParquetRecordBatchStream<Box<dyn AsyncFileReader>>,
+    batch: Option<RecordBatch>,
+    row_index: usize,
+    row_number: u64,
+    /// Uncompressed bytes of the row groups per row
+    row_byte_length: u64,
+}
+
+impl ParquetReader {
+    /// Starts after the first `skip_rows` rows, without reading the row groups holding only skipped rows
+    pub async fn new(input: Box<dyn AsyncFileReader>, skip_rows: u64) -> anyhow::Result<Self> {
+        let mut builder = ParquetRecordBatchStreamBuilder::new(input).await?;
+
+        let rows = builder.metadata().file_metadata().num_rows() as u64;
+        log::info!("Parquet file has {rows} rows in {row_groups} row groups", row_groups=builder.metadata().num_row_groups());
+
+        let total_byte_size = builder.metadata().row_groups().iter().map(|metadata| metadata.total_byte_size() as u64).sum::<u64>();
+        let row_byte_length = total_byte_size / rows.max(1);
+
+        if skip_rows > rows {
+            anyhow::bail!("The checkpoint is past the end of the file, at record {skip_rows}");
+        }
+
+        if skip_rows > 0 {
+            let mut group_start = 0;
+            let mut row_groups = Vec::new();
+            let mut offset = 0;
+
+            for (row_group, metadata) in builder.metadata().row_groups().iter().enumerate() {
+                let group_end = group_start + metadata.num_rows() as u64;
+                if group_end > skip_rows {
+                    if row_groups.is_empty() {
+                        offset = skip_rows - group_start;
+                    }
+                    row_groups.push(row_group);
+                }
+                group_start = group_end;
+            }
+
+            log::info!("Skipping {skipped} row groups and {offset} rows",
+                skipped=builder.metadata().num_row_groups() - row_groups.len());
+            builder = builder.with_row_groups(row_groups).with_offset(offset as usize);
+        }
+
+        let parquet_reader = ParquetReader {
+            stream: builder.build()?,
+            batch: None,
+            row_index: 0,
+            row_number: skip_rows,
+            row_byte_length,
+        };
+
+        Ok(parquet_reader)
+    }
+
+    pub async fn next_record(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        loop {
+            if let Some(batch) = &self.batch {
+                if self.row_index < batch.num_rows() {
+                    let row = record_batch_row_to_value(batch, self.row_index)?;
+                    self.row_index += 1;
+                    self.row_number += 1;
+                    let mut record = SourceRecord::new(self.row_number, row);
+                    record.byte_length = self.row_byte_length;
+                    return Ok(Some(record));
+                }
+            }
+
+            match self.stream.next().await {
+                Some(batch) => {
+                    self.batch = Some(batch?);
+                    self.row_index = 0;
+                },
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+
+fn record_batch_row_to_value(batch: &RecordBatch, row: usize) -> anyhow::Result<Value> {
+    let schema = batch.schema();
+    let fields = schema.fields().iter().zip(batch.columns()).map(|(field, column)| {
+        Ok((field.name().to_owned(), array_value_to_json(column.as_ref(), row)?))
+    }).collect::<anyhow::Result<Map<_, _>>>()?;
+
+    Ok(Value::Object(fields))
+}
+
+/// Converts one value of an Arrow array to JSON. Temporal and decimal values become their ISO 8601 and
+/// decimal text forms, and binary values become hexadecimal strings, to be parsed after the column types.
+fn array_value_to_json(array: &dyn Array, row: usize) -> anyhow::Result<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    let value = match array.data_type() {
+        DataType::Boolean => Value::Bool(array.as_boolean().value(row)),
+        DataType::Int8 => Value::from(array.as_primitive::<Int8Type>().value(row)),
+        DataType::Int16 => Value::from(array.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => Value::from(array.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => Value::from(array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::from(array.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => Value::from(array.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => Value::from(array.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => Value::from(array.as_primitive::<UInt64Type>().value(row)),
+        DataType::Float16 => float_to_json(array.as_primitive::<Float16Type>().value(row).to_f64()),
+        DataType::Float32 => float_to_json(f64::from(array.as_primitive::<Float32Type>().value(row))),
+        DataType::Float64 => float_to_json(array.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::String(array.as_string::<i32>().value(row).to_owned()),
+        DataType::LargeUtf8 => Value::String(array.as_string::<i64>().value(row).to_owned()),
+        DataType::Utf8View => Value::String(array.as_string_view().value(row).to_owned()),
+        DataType::Binary => bytes_to_json(array.as_binary::<i32>().value(row)),
+        DataType::LargeBinary => bytes_to_json(array.as_binary::<i64>().value(row)),
+        DataType::BinaryView => bytes_to_json(array.as_binary_view().value(row)),
+        DataType::FixedSizeBinary(_) => bytes_to_json(array.as_fixed_size_binary().value(row)),
+        DataType::Timestamp(TimeUnit::Second, _) => timestamp_to_json(array.as_primitive::<TimestampSecondType>().value_as_datetime(row)),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => timestamp_to_json(array.as_primitive::<TimestampMillisecondType>().value_as_datetime(row)),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => timestamp_to_json(array.as_primitive::<TimestampMicrosecondType>().value_as_datetime(row)),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => timestamp_to_json(array.as_primitive::<TimestampNanosecondType>().value_as_datetime(row)),
+        DataType::List(_) => elements_to_json(array.as_list::<i32>().value(row).as_ref())?,
+        DataType::LargeList(_) => elements_to_json(array.as_list::<i64>().value(row).as_ref())?,
+        DataType::FixedSizeList(_, _) => elements_to_json(array.as_fixed_size_list().value(row).as_ref())?,
+        DataType::Map(_, _) => {
+            let entries = array.as_map().value(row);
+            let (keys, values) = (entries.column(0), entries.column(1));
+
+            let map = (0..entries.len()).map(|index| {
+                let key = match array_value_to_json(keys.as_ref(), index)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                Ok((key, array_value_to_json(values.as_ref(), index)?))
+            }).collect::<anyhow::Result<Map<_, _>>>()?;
+
+            Value::Object(map)
+        },
+        DataType::Struct(fields) => {
+            let struct_array = array.as_struct();
+            let object = fields.iter().zip(struct_array.columns()).map(|(field, column)| {
+                Ok((field.name().to_owned(), array_value_to_json(column.as_ref(), row)?))
+            }).collect::<anyhow::Result<Map<_, _>>>()?;
+
+            Value::Object(object)
+        },
+        _ => {
+            let formatter = ArrayFormatter::try_new(array, &FormatOptions::default())?;
+            Value::String(formatter.value(row).try_to_string()?)
+        },
+    };
+
+    Ok(value)
+}
+
+fn elements_to_json(elements: &dyn Array) -> anyhow::Result<Value> {
+    let values = (0..elements.len())
+        .map(|index| array_value_to_json(elements, index))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Value::Array(values))
+}
+
+/// Arrow timestamps are instants since the unix epoch, whatever their time zone annotation
+fn timestamp_to_json(date_time: Option<chrono::NaiveDateTime>) -> Value {
+    date_time.map(date_time_to_json).unwrap_or(Value::Null)
+}
@@ -0,0 +1,88 @@
+use parquet::arrow::async_reader::AsyncFileReader;
+use tokio::io::AsyncBufRead;
+
+use crate::entities::{FilePosition, SourceProgress};
+
+use super::{compression::Compression, file_type::FileType, source_options::SourceOptions, source_record::SourceRecord, csv_reader::CsvReader, json_reader::JsonReader, parquet_reader::ParquetReader,
+            avro_reader::AvroReader};
+
+/// Decodes the records of an async source according to its file type
+pub enum RecordsReader<R> {
+    Json(JsonReader<R>),
+    Csv(Box<CsvReader<R>>),
+    Parquet(Box<ParquetReader>),
+    Avro(AvroReader),
+}
+
+impl<R: AsyncBufRead + Unpin + Send + 'static> RecordsReader<R> {
+    pub async fn new(reader: R, source_options: &SourceOptions) -> anyhow::Result<Self> {
+        let records_reader = match source_options.file_type {
+            FileType::JSON => RecordsReader::Json(JsonReader::new(reader, source_options.json_pointer.clone())),
+            FileType::CSV => RecordsReader::Csv(Box::new(CsvReader::new(reader, &source_options.csv_dialect).await?)),
+            FileType::Parquet => anyhow::bail!("Parquet files cannot be read as a stream"),
+            FileType::Avro => RecordsReader::Avro(AvroReader::new(reader)),
+        };
+
+        Ok(records_reader)
+    }
+
+    /// Reads a Parquet file from row `skip_rows`
+    pub async fn new_parquet(input: Box<dyn AsyncFileReader>, skip_rows: u64) -> anyhow::Result<Self> {
+        let parquet_reader = ParquetReader::new(input, skip_rows).await?;
+        Ok(RecordsReader::Parquet(Box::new(parquet_reader)))
+    }
+
+    /// Where reading can continue after the records acknowledged in `progress`, for CSV and JSON files stored
+    /// uncompressed, whose byte offsets are those of the file. `file_size` tells a checkpoint of another file.
+    pub fn resume_position(&self, progress: &SourceProgress, compression: Compression, file_size: u64) -> anyhow::Result<Option<FilePosition>> {
+        let Some(position) = progress.position.filter(|_| progress.records > 0 && compression == Compression::None) else {
+            return Ok(None);
+        };
+
+        if !matches!(self, RecordsReader::Json(_) | RecordsReader::Csv(_)) {
+            return Ok(None);
+        }
+
+        if position.byte_offset > file_size {
+            anyhow::bail!("The checkpoint is past the end of the file, at byte {offset} of {file_size}", offset=position.byte_offset);
+        }
+
+        Ok(Some(position))
+    }
+
+    /// Continues with `reader`, which starts at a position given by `resume_position`
+    pub fn resume_at(&mut self, reader: R, position: FilePosition, records: u64) -> anyhow::Result<()> {
+        match self {
+            RecordsReader::Json(json_reader) => json_reader.resume_at(reader, position, records),
+            RecordsReader::Csv(csv_reader) => csv_reader.resume_at(reader, position, records),
+            RecordsReader::Parquet(_) | RecordsReader::Avro(_) => anyhow::bail!("Only CSV and JSON files resume at a byte offset"),
+        }
+
+        log::info!("Reading on from byte {offset} (line {line_number})", offset=position.byte_offset, line_number=position.line_number);
+        Ok(())
+    }
+
+    /// Reads past the first `records` records, for the files that cannot resume at a byte offset
+    pub async fn skip_records(&mut self, records: u64) -> anyhow::Result<()> {
+        if records > 0 {
+            log::info!("Reading past the first {records} records");
+        }
+
+        for _ in 0..records {
+            if self.next_record().await?.is_none() {
+                anyhow::bail!("The checkpoint is past the end of the file, at record {records}");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn next_record(&mut self) -> anyhow::Result<Option<SourceRecord>> {
+        match self {
+            RecordsReader::Json(json_reader) => json_reader.next_record().await,
+            RecordsReader::Csv(csv_reader) => csv_reader.next_record().await,
+            RecordsReader::Parquet(parquet_reader) => parquet_reader.next_record().await,
+            RecordsReader::Avro(avro_reader) => avro_reader.next_record().await,
+        }
+    }
+}
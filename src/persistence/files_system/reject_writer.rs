@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use aws_sdk_s3::types::ByteStream;
+use tokio::{fs::File, io::{AsyncWriteExt, BufWriter}, sync::Mutex};
+
+use crate::entities::RejectedRow;
+
+use super::{is_s3_path, s3_client_for, s3_dataset::split_bucket_and_key};
+
+/// Writes the rejected rows as JSON lines to a local file, or under an `s3://` prefix as numbered
+/// `part-NNNNN.jsonl` objects. Rows for S3 are staged in a local file, uploaded as a new part on every flush.
+/// A resumed run appends to a local file, or adds parts after the existing ones.
+pub struct RejectWriter {
+    reject_path: String,
+    file: Mutex<RejectFile>,
+    staging_path: Option<PathBuf>,
+    s3_client: Option<aws_sdk_s3::Client>,
+}
+
+struct RejectFile {
+    writer: BufWriter<File>,
+    /// Rows written since the last upload of a part
+    pending_rows: usize,
+    /// Number of the next part uploaded to S3
+    next_part: u32,
+}
+
+impl RejectWriter {
+    pub async fn create(reject_path: &str, s3_client: Option<&aws_sdk_s3::Client>, append: bool) -> anyhow::Result<Self> {
+        let staging_path = is_s3_path(reject_path).then(|| {
+            std::env::temp_dir().join(format!("scylladb-uploader-rejects-{}-{:08x}.jsonl", std::process::id(), rand::random::<u32>()))
+        });
+
+        let next_part = match &staging_path {
+            Some(_) => prepare_parts(reject_path, s3_client_for(s3_client, reject_path)?, append).await?,
+            None => 0,
+        };
+
+        let file_path = staging_path.clone().unwrap_or_else(|| PathBuf::from(reject_path));
+        let file = open_file(&file_path, append && staging_path.is_none()).await?;
+
+        log::info!("Writing rejected rows to {reject_path}");
+
+        let reject_writer = RejectWriter {
+            reject_path: reject_path.to_owned(),
+            file: Mutex::new(RejectFile { writer: BufWriter::new(file), pending_rows: 0, next_part }),
+            staging_path,
+            s3_client: s3_client.cloned(),
+        };
+
+        Ok(reject_writer)
+    }
+
+    pub async fn write(&self, rejected_rows: &[RejectedRow]) -> anyhow::Result<()> {
+        let mut file = self.file.lock().await;
+
+        for rejected_row in rejected_rows {
+            file.writer.write_all(rejected_row.to_json_line().as_bytes()).await?;
+            file.writer.write_all(b"\n").await?;
+        }
+        file.pending_rows += rejected_rows.len();
+
+        Ok(())
+    }
+
+    /// Makes the rows written so far durable: flushes the local file, or uploads the staged rows as a new part
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let mut file = self.file.lock().await;
+        file.writer.flush().await?;
+
+        let Some(staging_path) = &self.staging_path else {
+            return Ok(());
+        };
+
+        if file.pending_rows == 0 {
+            return Ok(());
+        }
+
+        let (bucket, prefix) = split_bucket_and_key(&self.reject_path)?;
+        let key = part_key(&prefix, file.next_part);
+        let s3_client = s3_client_for(self.s3_client.as_ref(), &self.reject_path)?;
+
+        s3_client
+            .put_object()
+            .bucket(&bucket)
+            .key(&key)
+            .body(ByteStream::from_path(staging_path).await?)
+            .send()
+            .await
+            .with_context(|| format!("Cannot upload rejects to s3://{bucket}/{key}"))?;
+
+        log::debug!("Uploaded {rows} rejected rows to s3://{bucket}/{key}", rows=file.pending_rows);
+
+        file.writer = BufWriter::new(open_file(staging_path, false).await?);
+        file.pending_rows = 0;
+        file.next_part += 1;
+
+        Ok(())
+    }
+
+    /// Flushes the rejects, uploading the last part when they go to S3
+    pub async fn close(&self) -> anyhow::Result<()> {
+        self.flush().await?;
+
+        if let Some(staging_path) = &self.staging_path {
+            tokio::fs::remove_file(staging_path).await?;
+        }
+
+        Ok(())
+    }
+}
+
+
+async fn open_file(file_path: &Path, append: bool) -> anyhow::Result<File> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(file_path).await
+        .with_context(|| format!("Cannot create rejects file {}", file_path.display()))?;
+
+    Ok(file)
+}
+
+fn parts_prefix(prefix: &str) -> String {
+    format!("{}/part-", prefix.trim_end_matches('/'))
+}
+
+fn part_key(prefix: &str, part: u32) -> String {
+    format!("{}{part:05}.jsonl", parts_prefix(prefix))
+}
+
+/// Returns the number of the first part to upload: the one after the existing parts when appending, or 0 after
+/// deleting them, as a local rejects file is truncated
+async fn prepare_parts(reject_path: &str, s3_client: &aws_sdk_s3::Client, append: bool) -> anyhow::Result<u32> {
+    let (bucket, prefix) = split_bucket_and_key(reject_path)?;
+    let parts_prefix = parts_prefix(&prefix);
+
+    let mut part_keys = Vec::new();
+    let mut continuation_token = None;
+
+    loop {
+        let page = s3_client
+            .list_objects_v2()
+            .bucket(&bucket)
+            .prefix(&parts_prefix)
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .with_context(|| format!("Cannot list the rejects under {reject_path}"))?;
+
+        part_keys.extend(page.contents().unwrap_or_default().iter().filter_map(|object| object.key()).map(str::to_owned));
+
+        continuation_token = page.next_continuation_token().map(str::to_owned);
+        if !page.is_truncated() || continuation_token.is_none() {
+            break;
+        }
+    }
+
+    if append {
+        let last_part = part_keys.iter()
+            .filter_map(|key| key[parts_prefix.len()..].strip_suffix(".jsonl")?.parse::<u32>().ok())
+            .max();
+        return Ok(last_part.map(|last_part| last_part + 1).unwrap_or(0));
+    }
+
+    for key in &part_keys {
+        s3_client.delete_object().bucket(&bucket).key(key).send().await
+            .with_context(|| format!("Cannot delete the earlier rejects s3://{bucket}/{key}"))?;
+    }
+
+    if !part_keys.is_empty() {
+        log::info!("Deleted {count} earlier parts of rejects under {reject_path}", count=part_keys.len());
+    }
+
+    Ok(0)
+}
@@ -1,65 +1,79 @@
-use std::{borrow::Cow, sync::Arc};
+use std::sync::Arc;
 
+use anyhow::Context;
 use async_trait::async_trait;
+use aws_config::{default_provider::{credentials::DefaultCredentialsChain, region::DefaultRegionChain}, sts::AssumeRoleProvider};
 use aws_credential_types::provider::SharedCredentialsProvider;
 use aws_sdk_s3::{Credentials, Region, types::ByteStream};
-use tokio::io::Lines;
-use tokio::{sync::RwLock};
+use tokio::sync::Mutex;
+use glob::{MatchOptions, Pattern};
 use tokio_util::io::StreamReader;
-use url::Url;
 use tokio::io::BufReader;
-use tokio::io::AsyncBufReadExt;
 
-use super::{file_type::FileType, dataset_ext::DatasetExt};
+use crate::entities::SourceProgress;
+
+use super::{file_type::FileType, compression::{SourceReader, decompress}, dataset_ext::DatasetExt, records_reader::RecordsReader,
+            s3_object_reader::S3ObjectReader, source_options::SourceOptions, source_record::SourceRecord, is_glob_pattern, is_hidden_file,
+            s3_client_for};
+
+type S3Reader = BufReader<StreamReader<ByteStream, bytes::Bytes>>;
+
+/// Connection settings of the S3-compatible object storage
+#[derive(Debug, Clone)]
+pub struct S3Options {
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub profile: Option<String>,
+    pub role_arn: Option<String>,
+    /// Address buckets as `endpoint/bucket/key` rather than `bucket.endpoint/key`
+    pub force_path_style: bool,
+}
 
 pub struct S3Dataset {
-    lines: Arc<RwLock<Lines<tokio::io::BufReader<StreamReader<ByteStream, bytes::Bytes>>>>>,
-    file_type: FileType,
-    csv_header: Option<String>,
+    records: Arc<Mutex<RecordsReader<SourceReader>>>,
 }
 
 impl S3Dataset {
-    pub async fn new(source_path: &str, file_type: &FileType,
-                    access_key: Option<String>, secret_key: Option<String>, region_name: Option<String>, endpoint_url: Option<String>) -> anyhow::Result<Self> {
-        
+    /// Opens the object after the records acknowledged in `progress`, with a ranged request for the text files
+    /// that resume at a byte offset
+    pub async fn new(source_path: &str, source_options: &SourceOptions, progress: &SourceProgress) -> anyhow::Result<Self> {
         let (bucket, key) = split_bucket_and_key(source_path)?;
-        let s3_config = make_s3_config(access_key, secret_key, region_name, endpoint_url);
-        let s3_client = make_s3_client(s3_config)?;
+        let s3_client = s3_client_for(source_options.s3_client.as_ref(), source_path)?;
 
-        match file_type {
-            FileType::JSON => S3Dataset::load_json(&bucket, &key, &s3_client).await,
-            FileType::CSV => S3Dataset::load_csv(source_path, &key, &s3_client).await,
-        }
-    }
+        let records = match source_options.file_type {
+            FileType::Parquet => {
+                let object_reader = S3ObjectReader::new(&bucket, &key, s3_client).await?;
+                RecordsReader::new_parquet(Box::new(object_reader), progress.records).await?
+            },
+            _ => {
+                let (reader, object_size) = open_s3_file(&bucket, &key, s3_client, 0).await?;
+                let (reader, compression) = decompress(reader, &key, source_options.compression).await?;
+                let mut records = RecordsReader::new(reader, source_options).await?;
 
-    async fn load_json(bucket_name: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> anyhow::Result<Self> {
-        let lines = open_s3_file(bucket_name, key, s3_client).await?;
-        let lines_lock = Arc::new(RwLock::new(lines));
+                match records.resume_position(progress, compression, object_size)? {
+                    // A range starting at the end of the object is not satisfiable
+                    Some(position) if position.byte_offset == object_size => {
+                        records.resume_at(Box::new(tokio::io::empty()), position, progress.records)?;
+                    },
+                    Some(position) => {
+                        let (reader, _) = open_s3_file(&bucket, &key, s3_client, position.byte_offset).await?;
+                        records.resume_at(Box::new(reader), position, progress.records)?;
+                    },
+                    None => records.skip_records(progress.records).await?,
+                }
 
-        let dataset = Self {
-            lines: lines_lock,
-            file_type: FileType::JSON,
-            csv_header: None
+                records
+            },
         };
 
-        Ok(dataset)
-    }
-
-    async fn load_csv(bucket_name: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> anyhow::Result<Self> {
-        let mut lines = open_s3_file(bucket_name, key, s3_client).await?;
-        let csv_header = lines.next_line().await?;
-        
-        let lines_lock = Arc::new(RwLock::new(lines));
-
-        let database = S3Dataset {
-            file_type: FileType::CSV,
-            lines: lines_lock,
-            csv_header
+        let dataset = S3Dataset {
+            records: Arc::new(Mutex::new(records)),
         };
 
-        Ok(database)
+        Ok(dataset)
     }
-
 }
 
 #[async_trait]
@@ -67,87 +81,177 @@ impl DatasetExt for S3Dataset {
     
     type DatasetType = Self;
 
-    async fn next_line(&self) -> anyhow::Result<Option<serde_json::Value>> {
-        let mut unlocked_lines = self.lines.write().await;
-        
-        if let Some(current_line) = unlocked_lines.next_line().await? {
-            match self.file_type {
-                FileType::JSON => {
-                    let value = serde_json::from_str(&current_line)?;
-                    Ok(value)
-                },
-                FileType::CSV => {
-                    let csv_line_with_header = self.csv_header.clone().unwrap() + "\n" + &current_line;
-                    let mut csv_reader = csv::Reader::from_reader(csv_line_with_header.as_bytes());
-                    let mut csv_iter = csv_reader.deserialize();
-
-                    let value: serde_json::Value = csv_iter.next().unwrap()?;
-                    Ok(Some(value))
-                }
+    async fn next_line(&self) -> anyhow::Result<Option<SourceRecord>> {
+        let mut unlocked_records = self.records.lock().await;
+        unlocked_records.next_record().await
+    }
+    
+}
+
+/// Lists the objects under a `s3://bucket/prefix/` path, skipping hidden entries, or the objects matching a
+/// glob pattern in the key. Returns `None` when `source_path` names a single object.
+pub async fn list_s3_objects(source_path: &str, s3_client: &aws_sdk_s3::Client) -> anyhow::Result<Option<Vec<String>>> {
+    let (bucket, key) = split_bucket_and_key(source_path)?;
+
+    let (prefix, pattern) = if is_glob_pattern(&key) {
+        let prefix_end = key.find(['*', '?', '[']).unwrap_or(key.len());
+        (key[..prefix_end].to_owned(), Some(Pattern::new(&key)?))
+    } else if key.is_empty() || key.ends_with('/') {
+        (key, None)
+    } else {
+        return Ok(None);
+    };
+
+    let scheme = source_path.split_once("://").map(|(scheme, _)| scheme).unwrap_or("s3");
+    let match_options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+
+    let mut object_paths = Vec::new();
+    let mut continuation_token = None;
+
+    loop {
+        let page = s3_client
+            .list_objects_v2()
+            .bucket(&bucket)
+            .prefix(&prefix)
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .with_context(|| format!("Cannot list s3://{bucket}/{prefix}"))?;
+
+        for object_key in page.contents().unwrap_or_default().iter().filter_map(|object| object.key()) {
+            let is_selected = match &pattern {
+                Some(pattern) => pattern.matches_with(object_key, match_options),
+                None => !object_key.ends_with('/') && !object_key[prefix.len()..].split('/').any(is_hidden_file),
+            };
+
+            if is_selected {
+                object_paths.push(format!("{scheme}://{bucket}/{object_key}"));
             }
-        } else {
-            Ok(None)
+        }
+
+        continuation_token = page.next_continuation_token().map(str::to_owned);
+        if !page.is_truncated() || continuation_token.is_none() {
+            break;
         }
     }
-    
+
+    log::info!("Listed {count} objects under s3://{bucket}/{prefix}", count=object_paths.len());
+
+    Ok(Some(object_paths))
 }
 
-fn make_s3_client(s3_config: aws_sdk_s3::Config) -> anyhow::Result<aws_sdk_s3::Client> { 
-    let client = aws_sdk_s3::Client::from_conf(s3_config);
-    Ok(client)
+/// Builds the client shared by every S3 access of a run: listing, reading sources, rejects and checkpoints
+pub async fn make_s3_client(s3_options: &S3Options) -> anyhow::Result<aws_sdk_s3::Client> {
+    let s3_config = make_s3_config(s3_options).await?;
+    Ok(aws_sdk_s3::Client::from_conf(s3_config))
 }
 
-fn make_s3_config(access_key: Option<String>, secret_key: Option<String>, region_name: Option<String>, endpoint_url: Option<String>) -> aws_sdk_s3::Config {
-    let credentials = Credentials::new(
-        access_key.unwrap_or(String::new()),
-        secret_key.unwrap_or(String::new()),
-        None,
-        None,
-        "InternalProvider"
-    );
-    
-    let credential_provider = SharedCredentialsProvider::new(credentials);
-    let region_name_cow = region_name.map(|region_name_| Cow::Owned(region_name_.to_owned()));
-    let region = region_name_cow.map(|region_name_cow_| Region::new(region_name_cow_));
+async fn make_s3_config(s3_options: &S3Options) -> anyhow::Result<aws_sdk_s3::Config> {
+    let region = make_region(s3_options).await;
+    let credential_provider = make_credentials_provider(s3_options, region.clone()).await?;
     
     let mut s3_config_builder = aws_sdk_s3::Config::builder().region(region);
     
-    s3_config_builder.set_force_path_style(Some(true));
-    s3_config_builder.set_endpoint_url(endpoint_url);
+    s3_config_builder.set_force_path_style(Some(s3_options.force_path_style));
+    s3_config_builder.set_endpoint_url(s3_options.endpoint.clone());
     s3_config_builder.set_credentials_provider(Some(credential_provider));
 
-    let s3_config = s3_config_builder.build();
+    Ok(s3_config_builder.build())
+}
 
-    s3_config
+/// The given region, or else the one of the environment or profile. S3-compatible stores like MinIO take any
+/// region, so one with an endpoint falls back to `us-east-1`, their default.
+async fn make_region(s3_options: &S3Options) -> Option<Region> {
+    if let Some(region) = &s3_options.region {
+        return Some(Region::new(region.clone()));
+    }
+
+    let mut region_chain_builder = DefaultRegionChain::builder();
+    if let Some(profile) = &s3_options.profile {
+        region_chain_builder = region_chain_builder.profile_name(profile);
+    }
+
+    let region = region_chain_builder.build().region().await;
+    match region {
+        None if s3_options.endpoint.is_some() => Some(Region::from_static("us-east-1")),
+        region => region,
+    }
 }
 
+/// Uses the given keys, or else the default chain of the AWS SDKs (environment, profile, web identity,
+/// container and instance metadata), and assumes the given role with them
+async fn make_credentials_provider(s3_options: &S3Options, region: Option<Region>) -> anyhow::Result<SharedCredentialsProvider> {
+    let base_provider = match (&s3_options.access_key, &s3_options.secret_key) {
+        (Some(access_key), Some(secret_key)) => {
+            let credentials = Credentials::new(access_key, secret_key, None, None, "InternalProvider");
+            SharedCredentialsProvider::new(credentials)
+        },
+        (None, None) => {
+            let mut chain_builder = DefaultCredentialsChain::builder();
+            chain_builder.set_region(region.clone());
+            if let Some(profile) = &s3_options.profile {
+                chain_builder = chain_builder.profile_name(profile);
+            }
 
-fn split_bucket_and_key(source_path: &str) -> anyhow::Result<(String, String)> {
-    let url = Url::parse(source_path)?;
-    if let Some(bucket) = url.host_str() {
-        let encoded_key = url.path().strip_prefix("/").unwrap_or("");
-        let key = urldecode::decode(encoded_key.to_string());
-        Ok((bucket.to_owned(), key.to_owned()))
-    } else {
+            log::info!("Using the default AWS credentials chain (profile {profile})",
+                profile=s3_options.profile.as_deref().unwrap_or("default"));
+            SharedCredentialsProvider::new(chain_builder.build().await)
+        },
+        _ => anyhow::bail!("S3 access key and secret access key must be given together"),
+    };
+
+    let credential_provider = match &s3_options.role_arn {
+        Some(role_arn) => {
+            let mut role_builder = AssumeRoleProvider::builder(role_arn).session_name("scylladb-uploader");
+            if let Some(region) = region {
+                role_builder = role_builder.region(region);
+            }
+
+            log::info!("Assuming role {role_arn}");
+            SharedCredentialsProvider::new(role_builder.build(base_provider))
+        },
+        None => base_provider,
+    };
+
+    Ok(credential_provider)
+}
+
+
+/// Splits by hand rather than as a URL, since `?` and `#` are glob and key characters here
+pub(super) fn split_bucket_and_key(source_path: &str) -> anyhow::Result<(String, String)> {
+    let location = source_path.split_once("://").map(|(_, location)| location).unwrap_or(source_path);
+    let (bucket, encoded_key) = location.split_once('/').unwrap_or((location, ""));
+
+    if bucket.is_empty() {
         anyhow::bail!("Invalid source-path: {source_path}")
     }
-    
+
+    let key = urldecode::decode(encoded_key.to_string());
+    Ok((bucket.to_owned(), key))
 }
 
-async fn open_s3_file(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> anyhow::Result<Lines<tokio::io::BufReader<StreamReader<ByteStream, bytes::Bytes>>>> {   
-    let stream = s3_client
+/// Reads an object from byte `start`, returning the reader with the number of bytes it will read
+async fn open_s3_file(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client, start: u64) -> anyhow::Result<(S3Reader, u64)> {
+    let range = (start > 0).then(|| format!("bytes={start}-"));
+
+    let object = s3_client
         .get_object()
         .bucket(bucket)
         .key(key)
+        .set_range(range)
         .send()
-        .await?
-        .body;
+        .await
+        .with_context(|| format!("Cannot open s3://{bucket}/{key}"))?;
+
+    let content_length = object.content_length().max(0) as u64;
 
     // Convert the stream into an AsyncRead
-    let stream_reader = StreamReader::new(stream);
-    let buff_reader = BufReader::new(stream_reader).lines();
+    let stream_reader = StreamReader::new(object.body);
+    let buff_reader = BufReader::new(stream_reader);
 
-    log::info!("Opening file s3://{bucket}/{filename}", bucket=bucket, filename=key);
+    if start == 0 {
+        log::info!("Opening file s3://{bucket}/{filename}", bucket=bucket, filename=key);
+    }
 
-    Ok(buff_reader)
+    Ok((buff_reader, content_length))
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Context;
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+use parquet::{arrow::async_reader::AsyncFileReader, errors::ParquetError, file::metadata::{ParquetMetaData, ParquetMetaDataReader}};
+
+/// Random access to an S3 object through ranged GET requests, for formats that cannot be read as a stream
+pub struct S3ObjectReader {
+    s3_client: aws_sdk_s3::Client,
+    bucket: String,
+    key: String,
+    size: usize,
+}
+
+impl S3ObjectReader {
+    pub async fn new(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> anyhow::Result<Self> {
+        let head = s3_client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .with_context(|| format!("Cannot open s3://{bucket}/{key}"))?;
+
+        log::info!("Opening file s3://{bucket}/{filename}", bucket=bucket, filename=key);
+
+        let object_reader = S3ObjectReader {
+            s3_client: s3_client.clone(),
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            size: usize::try_from(head.content_length())?,
+        };
+
+        Ok(object_reader)
+    }
+
+    async fn read_range(&self, range: Range<usize>) -> anyhow::Result<Bytes> {
+        if range.is_empty() {
+            return Ok(Bytes::new());
+        }
+
+        let body = self.s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .range(format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await?
+            .body;
+
+        Ok(body.collect().await?.into_bytes())
+    }
+}
+
+impl AsyncFileReader for S3ObjectReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, parquet::errors::Result<Bytes>> {
+        async move {
+            self.read_range(range).await.map_err(|error| ParquetError::External(error.into()))
+        }.boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, parquet::errors::Result<Arc<ParquetMetaData>>> {
+        async move {
+            let size = self.size;
+            let metadata = ParquetMetaDataReader::new()
+                .with_prefetch_hint(Some(64 * 1024))
+                .load_and_finish(self, size)
+                .await?;
+
+            Ok(Arc::new(metadata))
+        }.boxed()
+    }
+}
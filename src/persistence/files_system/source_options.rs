@@ -0,0 +1,12 @@
+use super::{compression::Compression, csv_dialect::CsvDialect, file_type::FileType, json_reader::JsonPointer};
+
+/// How every source file of a run is opened and decoded
+#[derive(Debug, Clone)]
+pub struct SourceOptions {
+    pub file_type: FileType,
+    pub compression: Compression,
+    pub csv_dialect: CsvDialect,
+    pub json_pointer: JsonPointer,
+    /// Client of the `s3://` sources, when any
+    pub s3_client: Option<aws_sdk_s3::Client>,
+}
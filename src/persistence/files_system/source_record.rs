@@ -0,0 +1,34 @@
+use serde_json::Value;
+
+use crate::entities::FilePosition;
+
+/// A record of a source file, with where it was found
+#[derive(Debug)]
+pub struct SourceRecord {
+    /// Line where the record starts in text files, or the record number in Parquet and Avro files
+    pub line_number: u64,
+    pub value: Result<Value, UnreadableRecord>,
+    /// Where the record ends in the text of the file, when reading can resume right after it
+    pub position: Option<FilePosition>,
+    /// Bytes of the source read for the record: its text in text files, an even share of the row groups in
+    /// Parquet files, and the bytes read since the previous record in Avro files, which come a block at a time
+    pub byte_length: u64,
+}
+
+/// A record that could not be decoded, while the records after it still can
+#[derive(Debug)]
+pub struct UnreadableRecord {
+    /// The source text of the record
+    pub text: String,
+    pub error: anyhow::Error,
+}
+
+impl SourceRecord {
+    pub fn new(line_number: u64, value: Value) -> Self {
+        SourceRecord { line_number, value: Ok(value), position: None, byte_length: 0 }
+    }
+
+    pub fn unreadable(line_number: u64, text: String, error: anyhow::Error) -> Self {
+        SourceRecord { line_number, value: Err(UnreadableRecord { text, error }), position: None, byte_length: 0 }
+    }
+}
@@ -1,4 +1,4 @@
 mod database;
 pub mod files_system;
 
-pub use database::DatabaseClient;
+pub use database::{ColumnBinding, ConsistencyLevel, DatabaseClient, FrameCompression, RetryOptions, RetryableError, SerialConsistencyLevel, SessionOptions, TlsOptions, WriteMode, WriteOptions};
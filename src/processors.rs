@@ -1,32 +1,310 @@
-use futures::stream::{FuturesUnordered, StreamExt};
+use std::{path::PathBuf, sync::Arc, time::{Duration, Instant}};
 
-use crate::persistence::{DatabaseClient, files_system::Dataset};
-use crate::persistence::files_system::DatasetExt;
+use anyhow::Context;
+use futures::stream::{self, TryStreamExt};
+use serde_json::Value;
+use tokio::sync::{Mutex, Semaphore};
 
+use crate::entities::{Checkpoint, ErrorBudget, FailureStage, RateLimit, RejectedRow, TransferError, TransferSummary};
+use crate::persistence::{DatabaseClient, files_system::{CheckpointStore, Dataset, RejectWriter, SourceRecord}};
+use crate::persistence::files_system::{DatasetExt, SourceOptions};
+use crate::rate_limiter::RateLimiter;
 
 
-pub async fn run_transference(database_client: &DatabaseClient, dataset: &Dataset,
-                              batch_size: u32, concurrent_batches_size: usize) -> anyhow::Result<()> {
+/// How the rows of the source files are grouped and sent to the database
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    pub batch_size: u32,
+    pub concurrent_batches: usize,
+    pub source_parallelism: usize,
+    pub error_budget: ErrorBudget,
+    /// Skip the files and records the saved checkpoint has as loaded
+    pub resume: bool,
+    pub checkpoint_interval: Duration,
+    pub rate_limit: RateLimit,
+    /// File whose `max-rows-per-second` and `max-bytes-per-second` settings change the rate limit while running
+    pub rate_control_file: Option<PathBuf>,
+}
 
-    let mut batch_futures = FuturesUnordered::new();
-       
-    while let Some(batch) = dataset.next_batch(batch_size).await? {
-        let batch_future = async {
-            let result = database_client.insert_batch(batch).await;
-            
-            if let Err(error) = result {
-                log::error!("An error occurred while inserting batch: {}", error);
+
+/// Transfers every source file, `source_parallelism` files at a time. All of them share the
+/// `concurrent_batches` batch permits, so the limit holds for the whole run. Logs a summary when done,
+/// and fails when the rows that could not be written exceed the error budget.
+///
+/// Batches are read and inserted by tasks of their own, so parsing and converting rows is spread over the
+/// runtime worker threads, while this future only keeps the accounting.
+///
+/// With a checkpoint store, the progress of every file is saved every `checkpoint_interval` and when the
+/// run ends, even when it fails, so that a resumed run skips what was already loaded.
+///
+/// The batches of all the files are paced under the rate limit, which the rate control file can change.
+pub async fn run_transferences(database_client: Arc<DatabaseClient>, source_paths: Vec<String>, source_options: &SourceOptions,
+                               transfer_options: &TransferOptions, reject_writer: Option<&RejectWriter>,
+                               checkpoint_store: Option<&CheckpointStore>) -> anyhow::Result<()> {
+
+    let checkpoint = match checkpoint_store {
+        Some(checkpoint_store) if transfer_options.resume => checkpoint_store.load().await?.unwrap_or_else(|| {
+            log::warn!("No checkpoint to resume from, loading every file from the start");
+            Checkpoint::default()
+        }),
+        _ => Checkpoint::default(),
+    };
+
+    let rate_limiter = Arc::new(RateLimiter::new(transfer_options.rate_limit));
+    if transfer_options.rate_limit != RateLimit::default() {
+        log::info!("Rate limit set to {rate_limit}", rate_limit=transfer_options.rate_limit);
+    }
+
+    let rate_control = transfer_options.rate_control_file.clone().map(|control_file| {
+        tokio::spawn(watch_rate_control_file(control_file, rate_limiter.clone(), transfer_options.rate_limit))
+    });
+
+    let transference = Transference {
+        database_client,
+        rate_limiter,
+        source_options,
+        transfer_options,
+        reject_writer,
+        checkpoint_store,
+        batch_permits: Semaphore::new(transfer_options.concurrent_batches.max(1)),
+        summary: TransferSummary::new(),
+        checkpoint: std::sync::Mutex::new(checkpoint),
+        last_checkpoint: Mutex::new(Instant::now()),
+    };
+    let transference = &transference;
+
+    let transferences = stream::iter(source_paths.into_iter().map(anyhow::Ok))
+        .map_ok(|source_path| async move {
+            transference.run_source(&source_path).await
+                .with_context(|| format!("Source {source_path}"))
+        })
+        .try_buffer_unordered(transfer_options.source_parallelism.max(1))
+        .try_collect::<()>()
+        .await;
+
+    if let Some(rate_control) = rate_control {
+        rate_control.abort();
+    }
+
+    let rejects_closed = match reject_writer {
+        Some(reject_writer) => reject_writer.close().await,
+        None => Ok(()),
+    };
+    let checkpoint_saved = transference.save_checkpoint().await;
+
+    transference.summary.log();
+    transferences?;
+    rejects_closed?;
+    checkpoint_saved?;
+
+    let (failed_rows, processed_rows) = (transference.summary.failed_rows(), transference.summary.read_rows());
+    if transfer_options.error_budget.is_exceeded(failed_rows, processed_rows) {
+        return Err(TransferError::WriteErrorsExceeded { failed_rows, processed_rows }.into());
+    }
+
+    Ok(())
+}
+
+
+/// State shared by the transferences of all source files of a run
+struct Transference<'a> {
+    database_client: Arc<DatabaseClient>,
+    rate_limiter: Arc<RateLimiter>,
+    source_options: &'a SourceOptions,
+    transfer_options: &'a TransferOptions,
+    reject_writer: Option<&'a RejectWriter>,
+    checkpoint_store: Option<&'a CheckpointStore>,
+    batch_permits: Semaphore,
+    summary: TransferSummary,
+    checkpoint: std::sync::Mutex<Checkpoint>,
+    /// When the checkpoint was last saved, locked while saving it
+    last_checkpoint: Mutex<Instant>,
+}
+
+impl Transference<'_> {
+    async fn run_source(&self, source_path: &str) -> anyhow::Result<()> {
+        let progress = self.checkpoint.lock().unwrap().progress(source_path).cloned().unwrap_or_default();
+        if progress.finished {
+            log::info!("Skipping {source_path}, loaded before");
+            return Ok(());
+        }
+
+        if progress.records > 0 {
+            log::info!("Resuming {source_path} after record {records} (line {line_number})",
+                records=progress.records, line_number=progress.line_number);
+        }
+
+        // Spawned, as files that cannot resume at a byte offset are read up to the checkpoint
+        let (resumed_path, source_options, resumed_progress) = (source_path.to_owned(), self.source_options.clone(), progress.clone());
+        let dataset = tokio::spawn(async move { Dataset::resume(&resumed_path, &source_options, &resumed_progress).await }).await?
+            .map_err(TransferError::Source)?;
+        let dataset = Arc::new(dataset);
+
+        self.run_dataset(&dataset, source_path, progress.records).await?;
+
+        self.checkpoint.lock().unwrap().finish_source(source_path);
+        self.summary.add_loaded_file();
+        log::info!("Finished loading {source_path}");
+
+        Ok(())
+    }
+
+    /// Reads and inserts the batches of a dataset. A batch is only read once it gets a permit, which it holds
+    /// until inserted, so the permits bound both the rows held in memory and the statements in flight.
+    /// `first_record` is the index of the first record left in the dataset.
+    async fn run_dataset(&self, dataset: &Arc<Dataset>, source_path: &str, first_record: u64) -> anyhow::Result<()> {
+        let batches = stream::try_unfold(first_record, |next_record| async move {
+            let permit = self.batch_permits.acquire().await?;
+
+            let (dataset, batch_size) = (dataset.clone(), self.transfer_options.batch_size);
+            let batch = tokio::spawn(async move { dataset.next_batch(batch_size).await }).await?
+                .map_err(TransferError::Source)?;
+            anyhow::Ok(batch.map(|batch| {
+                let following_record = next_record + batch.len() as u64;
+                ((batch, next_record, permit), following_record)
+            }))
+        });
+
+        batches.try_for_each_concurrent(None, |(batch, first_record, permit)| async move {
+            let batch_size = batch.len();
+            let last_line_number = batch.last().map(|record| record.line_number).unwrap_or_default();
+            let end_position = batch.last().and_then(|record| record.position);
+            let (rejected_rows, retries) = self.insert_batch(batch, source_path).await?;
+            drop(permit);
+
+            for rejected_row in &rejected_rows {
+                log::error!("Row at {source_path}:{line_number} failed to {stage}: {error:#}",
+                    line_number=rejected_row.line_number, stage=rejected_row.stage, error=rejected_row.error);
+            }
+
+            if let Some(reject_writer) = self.reject_writer {
+                reject_writer.write(&rejected_rows).await?;
             }
+
+            self.summary.add_batch(batch_size, &rejected_rows, retries);
+            self.checkpoint.lock().unwrap().acknowledge_batch(source_path, first_record, batch_size as u64, last_line_number, end_position);
+            self.save_checkpoint_periodically().await?;
+
+            let failed_rows = self.summary.failed_rows();
+            if self.transfer_options.error_budget.is_exceeded_early(failed_rows) {
+                return Err(TransferError::WriteErrorsExceeded { failed_rows, processed_rows: self.summary.read_rows() }.into());
+            }
+
+            Ok(())
+        }).await
+    }
+
+    async fn save_checkpoint_periodically(&self) -> anyhow::Result<()> {
+        let Ok(mut last_checkpoint) = self.last_checkpoint.try_lock() else {
+            return Ok(());
         };
 
-        batch_futures.push(batch_future);
+        if last_checkpoint.elapsed() >= self.transfer_options.checkpoint_interval {
+            self.write_checkpoint().await?;
+            *last_checkpoint = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    async fn save_checkpoint(&self) -> anyhow::Result<()> {
+        let _last_checkpoint = self.last_checkpoint.lock().await;
+        self.write_checkpoint().await
+    }
+
+    /// Saves the progress acknowledged so far. The rejects are flushed first, so the rows the checkpoint
+    /// counts as loaded are never missing from them.
+    async fn write_checkpoint(&self) -> anyhow::Result<()> {
+        let Some(checkpoint_store) = self.checkpoint_store else {
+            return Ok(());
+        };
 
-        if batch_futures.len() == concurrent_batches_size {
-            batch_futures.next().await.unwrap();
+        let checkpoint = self.checkpoint.lock().unwrap().clone();
+        if let Some(reject_writer) = self.reject_writer {
+            reject_writer.flush().await?;
         }
+
+        checkpoint_store.save(&checkpoint).await
     }
 
-    while let Some(_) = batch_futures.next().await {}
+    /// Inserts the readable records of the batch, returning the records that could not be read or written,
+    /// with the number of writes retried
+    async fn insert_batch(&self, batch: Vec<SourceRecord>, source_path: &str) -> anyhow::Result<(Vec<RejectedRow>, usize)> {
+        let mut rejected_rows = Vec::new();
+        let mut line_numbers = Vec::with_capacity(batch.len());
+        let mut rows = Vec::with_capacity(batch.len());
+        let mut byte_length = 0;
 
-    Ok(())
-}
\ No newline at end of file
+        for record in batch {
+            match record.value {
+                Ok(row) => {
+                    line_numbers.push(record.line_number);
+                    rows.push(row);
+                    byte_length += record.byte_length;
+                },
+                Err(unreadable) => rejected_rows.push(RejectedRow {
+                    payload: Value::String(unreadable.text),
+                    source_path: source_path.to_owned(),
+                    line_number: record.line_number,
+                    stage: FailureStage::Read,
+                    error: unreadable.error,
+                }),
+            }
+        }
+
+        if rows.is_empty() {
+            return Ok((rejected_rows, 0));
+        }
+
+        let (database_client, rate_limiter) = (self.database_client.clone(), self.rate_limiter.clone());
+        let (mut rows, outcome) = tokio::spawn(async move {
+            rate_limiter.acquire(rows.len(), byte_length).await;
+            let outcome = database_client.insert_batch(&rows).await;
+            (rows, outcome)
+        }).await?;
+        let outcome = outcome?;
+
+        rejected_rows.extend(outcome.failures.into_iter().map(|failure| RejectedRow {
+            payload: rows[failure.row_index].take(),
+            source_path: source_path.to_owned(),
+            line_number: line_numbers[failure.row_index],
+            stage: failure.stage,
+            error: failure.error,
+        }));
+
+        Ok((rejected_rows, outcome.retries))
+    }
+}
+
+
+/// Applies the rate limit settings of the control file over `rate_limit` whenever the file changes,
+/// checking it every second
+async fn watch_rate_control_file(control_file: PathBuf, rate_limiter: Arc<RateLimiter>, rate_limit: RateLimit) {
+    let mut last_modified = None;
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let Ok(modified) = tokio::fs::metadata(&control_file).await.and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        if last_modified == Some(modified) {
+            continue;
+        }
+        last_modified = Some(modified);
+
+        let controlled_rate_limit = tokio::fs::read_to_string(&control_file).await
+            .map_err(anyhow::Error::from)
+            .and_then(|text| rate_limit.with_control_file(&text));
+
+        match controlled_rate_limit {
+            Ok(controlled_rate_limit) if controlled_rate_limit != rate_limiter.rate_limit() => {
+                rate_limiter.set_rate_limit(controlled_rate_limit);
+                log::info!("Rate limit set to {controlled_rate_limit} by {control_file}", control_file=control_file.display());
+            },
+            Ok(_) => {},
+            Err(error) => log::warn!("Ignoring rate control file {control_file}: {error:#}", control_file=control_file.display()),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::{sync::watch, time::Instant};
+
+use crate::entities::RateLimit;
+
+/// Paces the batches of all the transferences of a run under a rate limit that can change while it runs.
+/// Each batch is scheduled after the ones before it, for as long as its rows and bytes take at the limit.
+pub struct RateLimiter {
+    state: Mutex<RateLimiterState>,
+    /// Tells the batches waiting for their turn that the limit changed
+    changes: watch::Sender<RateLimit>,
+}
+
+struct RateLimiterState {
+    rate_limit: RateLimit,
+    /// When the next batch may start
+    next_start: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate_limit: RateLimit) -> Self {
+        let state = RateLimiterState { rate_limit, next_start: Instant::now() };
+        let (changes, _) = watch::channel(rate_limit);
+        RateLimiter { state: Mutex::new(state), changes }
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        self.state.lock().unwrap().rate_limit
+    }
+
+    /// Changes the limit. The wait of the batches already scheduled, reserved at the old limit, is rescaled
+    /// to the new one, so that raising the limit speeds them up and removing it lets them all go.
+    pub fn set_rate_limit(&self, rate_limit: RateLimit) {
+        let mut state = self.state.lock().unwrap();
+        state.next_start = rescale(state.next_start, state.rate_limit, rate_limit);
+        state.rate_limit = rate_limit;
+        self.changes.send_replace(rate_limit);
+    }
+
+    /// Waits until `rows` rows, read from `bytes` bytes of their source files, may be written
+    pub async fn acquire(&self, rows: usize, bytes: u64) {
+        if self.rate_limit().is_unlimited() {
+            return;
+        }
+
+        let (mut start, mut reserved_rate_limit, mut changes) = {
+            let mut state = self.state.lock().unwrap();
+            let duration = rate_duration(rows as u64, state.rate_limit.rows_per_second)
+                .max(rate_duration(bytes, state.rate_limit.bytes_per_second));
+
+            let start = state.next_start.max(Instant::now());
+            state.next_start = start + duration;
+            (start, state.rate_limit, self.changes.subscribe())
+        };
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(start) => return,
+                Ok(()) = changes.changed() => {
+                    let rate_limit = *changes.borrow_and_update();
+                    start = rescale(start, reserved_rate_limit, rate_limit);
+                    reserved_rate_limit = rate_limit;
+                },
+            }
+        }
+    }
+}
+
+/// Time `amount` takes at `per_second`
+fn rate_duration(amount: u64, per_second: Option<u64>) -> Duration {
+    match per_second {
+        Some(per_second) => Duration::from_secs_f64(amount as f64 / per_second as f64),
+        None => Duration::ZERO,
+    }
+}
+
+/// Moves a time reserved at `old_limit` to when it falls at `new_limit`. The wait left is scaled by the
+/// largest ratio of the limits set in both, since either may be the one the reservation was paced by.
+fn rescale(start: Instant, old_limit: RateLimit, new_limit: RateLimit) -> Instant {
+    let ratio = [(old_limit.rows_per_second, new_limit.rows_per_second), (old_limit.bytes_per_second, new_limit.bytes_per_second)]
+        .into_iter()
+        .filter_map(|(old_limit, new_limit)| Some(old_limit? as f64 / new_limit? as f64))
+        .fold(0.0, f64::max);
+
+    let now = Instant::now();
+    now + start.saturating_duration_since(now).mul_f64(ratio)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows_per_second(rows_per_second: u64) -> RateLimit {
+        RateLimit { rows_per_second: Some(rows_per_second), bytes_per_second: None }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_batches_at_the_limit() {
+        let rate_limiter = RateLimiter::new(rows_per_second(10));
+        let started = Instant::now();
+
+        rate_limiter.acquire(10, 0).await;
+        rate_limiter.acquire(10, 0).await;
+        rate_limiter.acquire(5, 0).await;
+
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_batches_at_the_tighter_of_the_rows_and_bytes_limits() {
+        let rate_limiter = RateLimiter::new(RateLimit { rows_per_second: Some(10), bytes_per_second: Some(1000) });
+        let started = Instant::now();
+
+        rate_limiter.acquire(10, 500).await;
+        rate_limiter.acquire(10, 3000).await;
+        rate_limiter.acquire(1, 0).await;
+
+        assert_eq!(started.elapsed(), Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rescales_the_batches_scheduled_at_the_old_limit() {
+        let rate_limiter = RateLimiter::new(rows_per_second(10));
+        let started = Instant::now();
+
+        rate_limiter.acquire(10, 0).await;
+        rate_limiter.acquire(10, 0).await;
+        rate_limiter.set_rate_limit(rows_per_second(20));
+
+        // The second second reserved at 10 rows/s takes half of it at 20 rows/s
+        rate_limiter.acquire(10, 0).await;
+        assert_eq!(started.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rescales_the_batches_already_waiting() {
+        let rate_limiter = RateLimiter::new(rows_per_second(10));
+        let started = Instant::now();
+        rate_limiter.acquire(10, 0).await;
+
+        let waiting = async {
+            rate_limiter.acquire(10, 0).await;
+            started.elapsed()
+        };
+        let slowing = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            rate_limiter.set_rate_limit(rows_per_second(5));
+        };
+
+        // Half a second of the second left at 10 rows/s is a second at 5 rows/s
+        let (waited, _) = tokio::join!(waiting, slowing);
+        assert_eq!(waited, Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lets_every_batch_go_when_the_limit_is_removed() {
+        let rate_limiter = RateLimiter::new(rows_per_second(1));
+        let started = Instant::now();
+        rate_limiter.acquire(100, 0).await;
+
+        let waiting = rate_limiter.acquire(100, 0);
+        let removing = async { rate_limiter.set_rate_limit(RateLimit::default()) };
+        tokio::join!(waiting, removing);
+
+        rate_limiter.acquire(100, 0).await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
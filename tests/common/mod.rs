@@ -0,0 +1,261 @@
+//! In-process stand-in for an S3-compatible object store, serving the few operations the uploader uses:
+//! GetObject (with ranges), HeadObject, PutObject, DeleteObject and ListObjectsV2, in path-style and virtual-host addressing.
+
+use std::{collections::{BTreeMap, HashMap}, convert::Infallible, future::Ready, net::SocketAddr, sync::{Arc, Mutex},
+          task::{Context, Poll}};
+
+use aws_smithy_client::{erase::DynConnector, http_connector::HttpConnector, hyper_ext::Adapter};
+use hyper::{Body, Method, Request, Response, StatusCode, client::connect::dns::Name, header, service::{make_service_fn, service_fn}};
+use scylladb_uploader::persistence::files_system::{S3Options, make_s3_client};
+
+/// Objects listed per ListObjectsV2 page, small enough for tests to span several pages
+pub const LIST_PAGE_SIZE: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: Method,
+    pub host: String,
+    pub bucket: String,
+    pub key: String,
+    pub query: HashMap<String, String>,
+    pub range: Option<String>,
+}
+
+#[derive(Default)]
+struct MockState {
+    objects: BTreeMap<(String, String), Vec<u8>>,
+    requests: Vec<MockRequest>,
+}
+
+pub struct MockS3 {
+    address: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockS3 {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request))) }
+        });
+
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        MockS3 { address, state }
+    }
+
+    pub fn put_object(&self, bucket: &str, key: &str, body: impl Into<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        state.objects.insert((bucket.to_owned(), key.to_owned()), body.into());
+    }
+
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().objects.get(&(bucket.to_owned(), key.to_owned())).cloned()
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// Options for path-style addressing against `127.0.0.1`, or for virtual-host addressing against
+    /// `localhost`, whose subdomains only resolve to the loopback address on some systems
+    pub fn s3_options(&self, force_path_style: bool) -> S3Options {
+        let host = if force_path_style { "127.0.0.1" } else { "localhost" };
+
+        S3Options {
+            access_key: Some("test-access-key".to_owned()),
+            secret_key: Some("test-secret-key".to_owned()),
+            region: Some("us-east-1".to_owned()),
+            endpoint: Some(format!("http://{host}:{port}", port=self.port())),
+            profile: None,
+            role_arn: None,
+            force_path_style,
+        }
+    }
+
+    pub async fn s3_client(&self, force_path_style: bool) -> aws_sdk_s3::Client {
+        make_s3_client(&self.s3_options(force_path_style)).await.unwrap()
+    }
+
+    /// Client addressing buckets as `bucket.localhost`, whatever the system resolves those names to: its
+    /// connections go to the mock for every host name
+    pub fn virtual_host_s3_client(&self) -> aws_sdk_s3::Client {
+        let s3_options = self.s3_options(false);
+        let credentials = aws_sdk_s3::Credentials::new(s3_options.access_key.unwrap(), s3_options.secret_key.unwrap(), None, None, "MockS3");
+
+        let connector = hyper::client::HttpConnector::new_with_resolver(LoopbackResolver);
+        let http_connector = HttpConnector::Prebuilt(Some(DynConnector::new(Adapter::builder().build(connector))));
+
+        let s3_config = aws_sdk_s3::Config::builder()
+            .region(aws_sdk_s3::Region::new(s3_options.region.unwrap()))
+            .endpoint_url(s3_options.endpoint.unwrap())
+            .force_path_style(false)
+            .credentials_provider(credentials)
+            .http_connector(http_connector)
+            .build();
+
+        aws_sdk_s3::Client::from_conf(s3_config)
+    }
+}
+
+
+/// Resolves every host name to the loopback address, where the mock listens
+#[derive(Clone)]
+struct LoopbackResolver;
+
+impl tower_service::Service<Name> for LoopbackResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _name: Name) -> Self::Future {
+        std::future::ready(Ok(vec![SocketAddr::from(([127, 0, 0, 1], 0))].into_iter()))
+    }
+}
+
+
+async fn handle(state: Arc<Mutex<MockState>>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let host = request.headers().get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.split(':').next())
+        .unwrap_or_default()
+        .to_owned();
+
+    let path = request.uri().path().trim_start_matches('/');
+    let (bucket, encoded_key) = match host.strip_suffix(".localhost") {
+        Some(bucket) => (bucket, path),
+        None => path.split_once('/').unwrap_or((path, "")),
+    };
+
+    let query = request.uri().query().unwrap_or_default().split('&')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            (urldecode::decode(name.to_owned()), urldecode::decode(value.to_owned()))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mock_request = MockRequest {
+        method: request.method().clone(),
+        bucket: bucket.to_owned(),
+        key: urldecode::decode(encoded_key.to_owned()),
+        range: request.headers().get(header::RANGE).and_then(|range| range.to_str().ok()).map(str::to_owned),
+        host,
+        query,
+    };
+
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(mock_request.clone());
+
+    let response = if mock_request.method == Method::PUT {
+        state.objects.insert((mock_request.bucket.clone(), mock_request.key.clone()), body.to_vec());
+        Response::new(Body::empty())
+    } else if mock_request.method == Method::DELETE {
+        state.objects.remove(&(mock_request.bucket.clone(), mock_request.key.clone()));
+        error_response(StatusCode::NO_CONTENT, "")
+    } else if mock_request.query.contains_key("list-type") {
+        list_objects(&state, &mock_request)
+    } else {
+        match state.objects.get(&(mock_request.bucket.clone(), mock_request.key.clone())) {
+            Some(object) => get_object(object, &mock_request),
+            None if mock_request.method == Method::HEAD => error_response(StatusCode::NOT_FOUND, ""),
+            None => error_response(StatusCode::NOT_FOUND, "NoSuchKey"),
+        }
+    };
+
+    Ok(response)
+}
+
+fn get_object(object: &[u8], request: &MockRequest) -> Response<Body> {
+    let range = request.range.as_deref()
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .map(|(start, end)| (start.parse::<usize>().unwrap(), end.parse::<usize>().map_or(object.len() - 1, |end| end.min(object.len() - 1))));
+
+    let response = Response::builder().header(header::CONTENT_TYPE, "application/octet-stream");
+
+    let response = match range {
+        Some((start, end)) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{}", object.len()))
+            .header(header::CONTENT_LENGTH, end + 1 - start)
+            .body(Body::from(object[start..=end].to_vec())),
+        None => response
+            .header(header::CONTENT_LENGTH, object.len())
+            .body(if request.method == Method::HEAD { Body::empty() } else { Body::from(object.to_vec()) }),
+    };
+
+    response.unwrap()
+}
+
+fn list_objects(state: &MockState, request: &MockRequest) -> Response<Body> {
+    if !state.objects.keys().any(|(bucket, _)| *bucket == request.bucket) {
+        return error_response(StatusCode::NOT_FOUND, "NoSuchBucket");
+    }
+
+    let prefix = request.query.get("prefix").cloned().unwrap_or_default();
+    let start = request.query.get("continuation-token").map(|token| token.parse::<usize>().unwrap()).unwrap_or(0);
+
+    let keys = state.objects.iter()
+        .filter(|((bucket, key), _)| *bucket == request.bucket && key.starts_with(&prefix))
+        .map(|((_, key), object)| (key, object.len()))
+        .collect::<Vec<_>>();
+
+    let page = keys.iter().skip(start).take(LIST_PAGE_SIZE).collect::<Vec<_>>();
+    let is_truncated = start + page.len() < keys.len();
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+         <Name>{bucket}</Name><Prefix>{prefix}</Prefix><KeyCount>{count}</KeyCount><MaxKeys>{LIST_PAGE_SIZE}</MaxKeys>\
+         <IsTruncated>{is_truncated}</IsTruncated>",
+        bucket=escape_xml(&request.bucket), prefix=escape_xml(&prefix), count=page.len());
+
+    if is_truncated {
+        xml.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", start + page.len()));
+    }
+
+    for (key, size) in page {
+        xml.push_str(&format!("<Contents><Key>{key}</Key><Size>{size}</Size></Contents>", key=escape_xml(key)));
+    }
+
+    xml.push_str("</ListBucketResult>");
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/xml")
+        .body(Body::from(xml))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, code: &str) -> Response<Body> {
+    let body = if code.is_empty() {
+        Body::empty()
+    } else {
+        Body::from(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>{code}</Code><Message>{code}</Message></Error>"))
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/xml")
+        .body(body)
+        .unwrap()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
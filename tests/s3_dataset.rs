@@ -0,0 +1,364 @@
+mod common;
+
+use std::sync::Arc;
+
+use arrow_array::{Int64Array, RecordBatch, StringArray};
+use hyper::Method;
+use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+use scylladb_uploader::entities::{Checkpoint, FailureStage, RejectedRow, SourceProgress};
+use scylladb_uploader::persistence::files_system::{CheckpointStore, Compression, CsvDialect, Dataset, DatasetExt, FileType, JsonPointer,
+                                                  RejectWriter, SourceOptions};
+use serde_json::{json, Value};
+
+use common::{LIST_PAGE_SIZE, MockS3};
+
+const BUCKET: &str = "uploader-test";
+
+fn source_options(file_type: FileType, s3_client: aws_sdk_s3::Client) -> SourceOptions {
+    SourceOptions {
+        file_type,
+        compression: Compression::Auto,
+        csv_dialect: CsvDialect { delimiter: b',', quote: b'"', escape: None, comment: None, column_names: None, null: String::new() },
+        json_pointer: JsonPointer::default(),
+        s3_client: Some(s3_client),
+    }
+}
+
+fn rejected_row(name: &str, line_number: u64) -> RejectedRow {
+    RejectedRow {
+        payload: json!({"id": "x", "name": name}),
+        source_path: format!("s3://{BUCKET}/exports/users.json"),
+        line_number,
+        stage: FailureStage::Convert,
+        error: anyhow::anyhow!("invalid digit found in string"),
+    }
+}
+
+async fn read_all(dataset: &Dataset) -> Vec<Value> {
+    let mut records = Vec::new();
+    while let Some(record) = dataset.next_line().await.unwrap() {
+        records.push(record.value.unwrap());
+    }
+
+    records
+}
+
+
+#[tokio::test]
+async fn reads_json_with_path_style_addressing() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.json", "{\"id\": 1, \"name\": \"Ana\"}\n{\"id\": 2, \"name\": \"Bruno\"}\n");
+
+    let options = source_options(FileType::JSON, mock_s3.s3_client(true).await);
+    let dataset = Dataset::load(&format!("s3://{BUCKET}/exports/users.json"), &options).await.unwrap();
+
+    assert_eq!(read_all(&dataset).await, vec![json!({"id": 1, "name": "Ana"}), json!({"id": 2, "name": "Bruno"})]);
+
+    let requests = mock_s3.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].host, "127.0.0.1");
+    assert_eq!(requests[0].bucket, BUCKET);
+    assert_eq!(requests[0].key, "exports/users.json");
+}
+
+#[tokio::test]
+async fn reads_csv() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.csv", "id,name,bio\n1,Ana,\"line one\nline two\"\n2,Bruno,\n");
+
+    let options = source_options(FileType::CSV, mock_s3.s3_client(true).await);
+    let dataset = Dataset::load(&format!("s3://{BUCKET}/exports/users.csv"), &options).await.unwrap();
+
+    assert_eq!(read_all(&dataset).await, vec![
+        json!({"id": "1", "name": "Ana", "bio": "line one\nline two"}),
+        json!({"id": "2", "name": "Bruno", "bio": null}),
+    ]);
+}
+
+#[tokio::test]
+async fn reads_parquet_with_ranged_requests() {
+    let mock_s3 = MockS3::start().await;
+
+    let batch = RecordBatch::try_from_iter([
+        ("id", Arc::new(Int64Array::from(vec![1, 2, 3])) as _),
+        ("name", Arc::new(StringArray::from(vec!["Ana", "Bruno", "Carla"])) as _),
+    ]).unwrap();
+
+    let mut parquet_file = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut parquet_file, batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    mock_s3.put_object(BUCKET, "exports/users.parquet", parquet_file);
+
+    let options = source_options(FileType::Parquet, mock_s3.s3_client(true).await);
+    let dataset = Dataset::load(&format!("s3://{BUCKET}/exports/users.parquet"), &options).await.unwrap();
+
+    assert_eq!(read_all(&dataset).await, vec![
+        json!({"id": 1, "name": "Ana"}),
+        json!({"id": 2, "name": "Bruno"}),
+        json!({"id": 3, "name": "Carla"}),
+    ]);
+
+    let requests = mock_s3.requests();
+    assert_eq!(requests[0].method, Method::HEAD);
+    assert!(requests[1..].iter().all(|request| request.method == Method::GET && request.range.is_some()));
+}
+
+#[tokio::test]
+async fn virtual_host_addressing() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.json", "{\"id\": 1}\n");
+
+    let options = source_options(FileType::JSON, mock_s3.virtual_host_s3_client());
+    let dataset = Dataset::load(&format!("s3://{BUCKET}/exports/users.json"), &options).await.unwrap();
+
+    assert_eq!(read_all(&dataset).await, vec![json!({"id": 1})]);
+
+    let requests = mock_s3.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].host, format!("{BUCKET}.localhost"));
+    assert_eq!(requests[0].bucket, BUCKET);
+    assert_eq!(requests[0].key, "exports/users.json");
+}
+
+#[tokio::test]
+async fn lists_prefix_across_pages() {
+    let mock_s3 = MockS3::start().await;
+    for part in 0..5 {
+        mock_s3.put_object(BUCKET, &format!("exports/part-{part}.json"), "{}\n");
+    }
+    mock_s3.put_object(BUCKET, "exports/_SUCCESS", "");
+    mock_s3.put_object(BUCKET, "exports/part-5.csv", "");
+    mock_s3.put_object(BUCKET, "other/part-0.json", "{}\n");
+
+    let s3_client = mock_s3.s3_client(true).await;
+    let source_paths = Dataset::list_sources(&format!("s3://{BUCKET}/exports/"), Some(&s3_client), &[], &[]).await.unwrap();
+
+    let expected_paths = (0..5).map(|part| format!("s3://{BUCKET}/exports/part-{part}.json"))
+        .chain([format!("s3://{BUCKET}/exports/part-5.csv")])
+        .collect::<Vec<_>>();
+    assert_eq!(source_paths, expected_paths);
+
+    let list_requests = mock_s3.requests().into_iter().filter(|request| request.query.contains_key("list-type")).count();
+    assert_eq!(list_requests, 7_usize.div_ceil(LIST_PAGE_SIZE));
+
+    let json_paths = Dataset::list_sources(&format!("s3://{BUCKET}/exports/*.json"), Some(&s3_client), &[], &[]).await.unwrap();
+    assert_eq!(json_paths, expected_paths[..5]);
+}
+
+#[tokio::test]
+async fn missing_object_is_an_error() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.json", "{}\n");
+
+    for file_type in [FileType::JSON, FileType::CSV, FileType::Parquet] {
+        let options = source_options(file_type, mock_s3.s3_client(true).await);
+        let error = Dataset::load(&format!("s3://{BUCKET}/exports/missing.json"), &options).await.err().unwrap();
+
+        assert!(format!("{error:#}").contains(&format!("Cannot open s3://{BUCKET}/exports/missing.json")), "{error:#}");
+    }
+
+    let error = Dataset::list_sources("s3://missing-bucket/exports/", Some(&mock_s3.s3_client(true).await), &[], &[]).await.err().unwrap();
+    assert!(format!("{error:#}").contains("Cannot list s3://missing-bucket/exports/"), "{error:#}");
+}
+
+#[tokio::test]
+async fn uploads_rejects_that_load_again() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.json", "{}\n");
+    let reject_path = format!("s3://{BUCKET}/rejects/users/");
+
+    let reject_writer = RejectWriter::create(&reject_path, Some(&mock_s3.s3_client(true).await), false).await.unwrap();
+    reject_writer.write(&[rejected_row("Ana", 3)]).await.unwrap();
+    reject_writer.close().await.unwrap();
+
+    let rejects: Value = serde_json::from_slice(&mock_s3.object(BUCKET, "rejects/users/part-00000.jsonl").unwrap()).unwrap();
+    assert_eq!(rejects, json!({
+        "payload": {"id": "x", "name": "Ana"},
+        "source": format!("s3://{BUCKET}/exports/users.json"),
+        "line": 3,
+        "stage": "convert",
+        "error": "invalid digit found in string",
+    }));
+
+    let mut options = source_options(FileType::JSON, mock_s3.s3_client(true).await);
+    options.json_pointer = "/payload".parse().unwrap();
+    let reject_paths = Dataset::list_sources(&reject_path, options.s3_client.as_ref(), &[], &[]).await.unwrap();
+    let dataset = Dataset::load(&reject_paths[0], &options).await.unwrap();
+
+    assert_eq!(read_all(&dataset).await, vec![json!({"id": "x", "name": "Ana"})]);
+}
+
+#[tokio::test]
+async fn uploads_rejects_in_parts_that_a_resumed_run_continues() {
+    let mock_s3 = MockS3::start().await;
+    mock_s3.put_object(BUCKET, "exports/users.json", "{}\n");
+    let s3_client = mock_s3.s3_client(true).await;
+    let reject_path = format!("s3://{BUCKET}/rejects/users");
+    let names = |part: u32| {
+        let part = mock_s3.object(BUCKET, &format!("rejects/users/part-{part:05}.jsonl"))?;
+        let rejects = serde_json::Deserializer::from_slice(&part).into_iter::<Value>()
+            .map(|reject| reject.unwrap()["payload"]["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        Some(rejects)
+    };
+
+    // A run flushing its rejects at a checkpoint, then interrupted without closing
+    let reject_writer = RejectWriter::create(&reject_path, Some(&s3_client), false).await.unwrap();
+    reject_writer.write(&[rejected_row("Ana", 1), rejected_row("Bruno", 2)]).await.unwrap();
+    reject_writer.flush().await.unwrap();
+    reject_writer.flush().await.unwrap();
+    reject_writer.write(&[rejected_row("Carla", 3)]).await.unwrap();
+    drop(reject_writer);
+
+    assert_eq!(names(0), Some(vec!["Ana".to_owned(), "Bruno".to_owned()]));
+    assert_eq!(names(1), None);
+
+    let reject_writer = RejectWriter::create(&reject_path, Some(&s3_client), true).await.unwrap();
+    reject_writer.write(&[rejected_row("Carla", 3)]).await.unwrap();
+    reject_writer.close().await.unwrap();
+
+    assert_eq!(names(0), Some(vec!["Ana".to_owned(), "Bruno".to_owned()]));
+    assert_eq!(names(1), Some(vec!["Carla".to_owned()]));
+
+    // A new run starts over
+    let reject_writer = RejectWriter::create(&reject_path, Some(&s3_client), false).await.unwrap();
+    reject_writer.write(&[rejected_row("Daniel", 1)]).await.unwrap();
+    reject_writer.close().await.unwrap();
+
+    assert_eq!(names(0), Some(vec!["Daniel".to_owned()]));
+    assert_eq!(names(1), None);
+}
+
+#[tokio::test]
+async fn saves_and_loads_checkpoints() {
+    let mock_s3 = MockS3::start().await;
+
+    let checkpoint_store = CheckpointStore::new(&format!("s3://{BUCKET}/checkpoints/users.json"), Some(&mock_s3.s3_client(true).await));
+    assert!(checkpoint_store.load().await.unwrap().is_none());
+
+    let source_path = format!("s3://{BUCKET}/exports/users.json");
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.acknowledge_batch(&source_path, 0, 100, 101, None);
+    checkpoint.acknowledge_batch(&source_path, 200, 100, 301, None);
+    checkpoint_store.save(&checkpoint).await.unwrap();
+
+    let progress = checkpoint_store.load().await.unwrap().unwrap().progress(&source_path).cloned().unwrap();
+    assert_eq!((progress.records, progress.line_number, progress.finished), (100, 101, false));
+}
+
+/// Progress of a run that inserted the first `records` records of `dataset`, through a saved checkpoint
+async fn acknowledge(checkpoint_store: &CheckpointStore, source_path: &str, dataset: &Dataset, records: u64) -> SourceProgress {
+    let mut last_record = None;
+    for _ in 0..records {
+        last_record = dataset.next_line().await.unwrap();
+    }
+
+    let last_record = last_record.unwrap();
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.acknowledge_batch(source_path, 0, records, last_record.line_number, last_record.position);
+    checkpoint_store.save(&checkpoint).await.unwrap();
+
+    checkpoint_store.load().await.unwrap().unwrap().progress(source_path).cloned().unwrap()
+}
+
+#[tokio::test]
+async fn resumes_csv_and_json_at_the_byte_offset_of_the_checkpoint() {
+    let mock_s3 = MockS3::start().await;
+    let s3_client = mock_s3.s3_client(true).await;
+    let checkpoint_store = CheckpointStore::new(&format!("s3://{BUCKET}/checkpoints/users.json"), Some(&s3_client));
+
+    let csv_file = "id,name,bio\n1,Ana,\"line one\nline two\"\n2,Bruno,\n3,Carla,\n";
+    let json_file = "{\"id\": 1}\n{\"id\": 2,\n \"name\": \"Bruno\"}\n{\"id\": 3}\n";
+    mock_s3.put_object(BUCKET, "exports/users.csv", csv_file);
+    mock_s3.put_object(BUCKET, "exports/users.json", json_file);
+
+    for (file_type, key, file, rest_of_file, resumed_record, resumed_line) in [
+        (FileType::CSV, "exports/users.csv", csv_file, "3,Carla,\n", json!({"id": "3", "name": "Carla", "bio": null}), 5),
+        (FileType::JSON, "exports/users.json", json_file, "\n{\"id\": 3}\n", json!({"id": 3}), 4),
+    ] {
+        let source_path = format!("s3://{BUCKET}/{key}");
+        let options = source_options(file_type, s3_client.clone());
+
+        let dataset = Dataset::load(&source_path, &options).await.unwrap();
+        let progress = acknowledge(&checkpoint_store, &source_path, &dataset, 2).await;
+        let position = progress.position.unwrap();
+        assert_eq!(&file[position.byte_offset as usize..], rest_of_file);
+
+        let dataset = Dataset::resume(&source_path, &options, &progress).await.unwrap();
+        let record = dataset.next_line().await.unwrap().unwrap();
+        assert_eq!((record.value.unwrap(), record.line_number), (resumed_record, resumed_line));
+        assert!(dataset.next_line().await.unwrap().is_none());
+
+        let last_request = mock_s3.requests().pop().unwrap();
+        assert_eq!((last_request.key.as_str(), last_request.range), (key, Some(format!("bytes={}-", position.byte_offset))));
+
+        // Every record acknowledged before the file was marked as loaded
+        let dataset = Dataset::load(&source_path, &options).await.unwrap();
+        let progress = acknowledge(&checkpoint_store, &source_path, &dataset, 3).await;
+        assert!(file[progress.position.unwrap().byte_offset as usize..].trim().is_empty());
+
+        let dataset = Dataset::resume(&source_path, &options, &progress).await.unwrap();
+        assert!(dataset.next_line().await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn resumes_json_arrays_by_reading_past_the_records() {
+    let mock_s3 = MockS3::start().await;
+    let s3_client = mock_s3.s3_client(true).await;
+    let checkpoint_store = CheckpointStore::new(&format!("s3://{BUCKET}/checkpoints/users.json"), Some(&s3_client));
+    mock_s3.put_object(BUCKET, "exports/users.json", "[{\"id\": 1},\n {\"id\": 2},\n {\"id\": 3}]\n");
+
+    let source_path = format!("s3://{BUCKET}/exports/users.json");
+    let options = source_options(FileType::JSON, s3_client);
+
+    let dataset = Dataset::load(&source_path, &options).await.unwrap();
+    let progress = acknowledge(&checkpoint_store, &source_path, &dataset, 2).await;
+    assert!(progress.position.is_none());
+
+    let dataset = Dataset::resume(&source_path, &options, &progress).await.unwrap();
+    assert_eq!(read_all(&dataset).await, vec![json!({"id": 3})]);
+    assert!(mock_s3.requests().iter().all(|request| request.range.is_none()));
+
+    let mut progress = progress;
+    progress.records = 4;
+    let error = Dataset::resume(&source_path, &options, &progress).await.err().unwrap();
+    assert_eq!(error.to_string(), "The checkpoint is past the end of the file, at record 4");
+}
+
+#[tokio::test]
+async fn resumes_parquet_at_the_row_group_of_the_checkpoint() {
+    let mock_s3 = MockS3::start().await;
+
+    let batch = RecordBatch::try_from_iter([
+        ("id", Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5])) as _),
+    ]).unwrap();
+
+    let mut parquet_file = Vec::new();
+    let properties = WriterProperties::builder().set_max_row_group_size(2).build();
+    let mut writer = ArrowWriter::try_new(&mut parquet_file, batch.schema(), Some(properties)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    mock_s3.put_object(BUCKET, "exports/users.parquet", parquet_file);
+
+    let source_path = format!("s3://{BUCKET}/exports/users.parquet");
+    let options = source_options(FileType::Parquet, mock_s3.s3_client(true).await);
+
+    for (records, resumed_ids) in [(3, vec![4, 5]), (4, vec![5]), (5, vec![])] {
+        let mut progress = SourceProgress::default();
+        progress.records = records;
+        let dataset = Dataset::resume(&source_path, &options, &progress).await.unwrap();
+
+        let mut resumed_records = Vec::new();
+        while let Some(record) = dataset.next_line().await.unwrap() {
+            resumed_records.push((record.value.unwrap()["id"].as_i64().unwrap(), record.line_number));
+        }
+        let expected_records = resumed_ids.iter().map(|id| (*id, *id as u64)).collect::<Vec<_>>();
+        assert_eq!(resumed_records, expected_records);
+    }
+
+    let mut progress = SourceProgress::default();
+    progress.records = 6;
+    assert!(Dataset::resume(&source_path, &options, &progress).await.is_err());
+}